@@ -0,0 +1,13 @@
+// Fragment shader
+// VertexOutput and main_vs come from fullscreen.wgsl
+
+[[group(0), binding(0)]]
+var t_input: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_input: sampler;
+
+// Copies the input into the current viewport, the pipeline blends it by alpha
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return textureSample(t_input, s_input, in.uv);
+}
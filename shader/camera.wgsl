@@ -0,0 +1,17 @@
+// Per-frame camera data, prepended to the shaders that bind it.
+// Must match CameraUniform in camera.rs field for field.
+
+[[block]]
+struct CameraUniform {
+    view: mat4x4<f32>;
+    proj: mat4x4<f32>;
+    view_proj: mat4x4<f32>;
+    inv_view: mat4x4<f32>;
+    inv_proj: mat4x4<f32>;
+    eye_position: vec4<f32>;
+    viewport_size: vec2<f32>;
+    znear: f32;
+    zfar: f32;
+    time: f32;
+};
+
@@ -0,0 +1,77 @@
+// Compute shader
+// Bins the point lights into an exponential froxel grid: one invocation per
+// cluster tests every light's sphere against the cluster's view space bounds.
+// Each cluster stores its light count followed by up to MAX_LIGHTS_PER_CLUSTER indices.
+
+// MAX_LIGHTS_PER_CLUSTER, ClusterUniforms, PointLight, Lights and Clusters come from cluster_common.wgsl
+
+[[group(0), binding(0)]]
+var<uniform> uniforms: ClusterUniforms;
+[[group(0), binding(1)]]
+var<storage> lights: [[access(read)]] Lights;
+[[group(0), binding(2)]]
+var<storage> clusters: [[access(read_write)]] Clusters;
+
+// View space depth of a slice boundary, slices are spaced exponentially
+fn slice_depth(slice: u32) -> f32 {
+    let t = f32(slice) / f32(uniforms.grid.z);
+    return uniforms.znear * pow(uniforms.zfar / uniforms.znear, t);
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn main_cs([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let cluster_count = uniforms.grid.x * uniforms.grid.y * uniforms.grid.z;
+    let cluster = id.x;
+    if (cluster >= cluster_count) {
+        return;
+    }
+
+    let tile_x = cluster % uniforms.grid.x;
+    let tile_y = (cluster / uniforms.grid.x) % uniforms.grid.y;
+    let slice = cluster / (uniforms.grid.x * uniforms.grid.y);
+
+    // Tile rows count down from the top of the screen, like fragment coordinates
+    let ndc_min = vec2<f32>(
+        f32(tile_x) / f32(uniforms.grid.x) * 2.0 - 1.0,
+        1.0 - f32(tile_y + 1u) / f32(uniforms.grid.y) * 2.0,
+    );
+    let ndc_max = vec2<f32>(
+        f32(tile_x + 1u) / f32(uniforms.grid.x) * 2.0 - 1.0,
+        1.0 - f32(tile_y) / f32(uniforms.grid.y) * 2.0,
+    );
+    let near = slice_depth(slice);
+    let far = slice_depth(slice + 1u);
+
+    // The frustum widens with depth, so the far plane corners bound x and y
+    // unless the tile straddles the centre line. Orthographic tiles don't widen.
+    let a = ndc_min * uniforms.view_extent;
+    let b = ndc_max * uniforms.view_extent;
+    var near_scale: f32 = near;
+    var far_scale: f32 = far;
+    if (uniforms.orthographic != 0u) {
+        near_scale = 1.0;
+        far_scale = 1.0;
+    }
+    let min_xy = min(min(a * near_scale, a * far_scale), min(b * near_scale, b * far_scale));
+    let max_xy = max(max(a * near_scale, a * far_scale), max(b * near_scale, b * far_scale));
+    // View space looks down -z
+    let aabb_min = vec3<f32>(min_xy, -far);
+    let aabb_max = vec3<f32>(max_xy, -near);
+
+    let base = cluster * (MAX_LIGHTS_PER_CLUSTER + 1u);
+    var count: u32 = 0u;
+    for (var i: u32 = 0u; i < uniforms.grid.w; i = i + 1u) {
+        if (count >= MAX_LIGHTS_PER_CLUSTER) {
+            break;
+        }
+        let light = lights.data[i];
+        let center = (uniforms.view * vec4<f32>(light.position, 1.0)).xyz;
+        let closest = clamp(center, aabb_min, aabb_max);
+        let offset = center - closest;
+        if (dot(offset, offset) <= light.radius * light.radius) {
+            clusters.data[base + 1u + count] = i;
+            count = count + 1u;
+        }
+    }
+    clusters.data[base] = count;
+}
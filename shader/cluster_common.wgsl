@@ -0,0 +1,33 @@
+// The light clusters' uniforms and buffers, prepended to the shaders that bin or read the lights.
+// ClusterUniforms must match ClusterUniforms in cluster.rs and PointLight must match PointLight
+// in light.rs. MAX_LIGHTS_PER_CLUSTER is generated from cluster.rs in front of this.
+
+[[block]]
+struct ClusterUniforms {
+    view: mat4x4<f32>;
+    eye_position: vec4<f32>;
+    grid: vec4<u32>;
+    screen_size: vec2<f32>;
+    znear: f32;
+    zfar: f32;
+    view_extent: vec2<f32>;
+    orthographic: u32;
+};
+
+struct PointLight {
+    position: vec3<f32>;
+    radius: f32;
+    color: vec3<f32>;
+    intensity: f32;
+};
+
+[[block]]
+struct Lights {
+    data: [[stride(32)]] array<PointLight>;
+};
+
+[[block]]
+struct Clusters {
+    data: [[stride(4)]] array<u32>;
+};
+
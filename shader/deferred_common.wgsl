@@ -0,0 +1,61 @@
+// The G-buffer, its uniforms and the shading model, prepended to the deferred lighting shaders.
+// DeferredUniforms must match DeferredUniforms in deferred.rs field for field.
+
+[[group(0), binding(0)]]
+var t_albedo: texture_2d<f32>;
+[[group(0), binding(1)]]
+var t_normal: texture_2d<f32>;
+[[group(0), binding(2)]]
+var t_material: texture_2d<f32>;
+[[group(0), binding(3)]]
+var t_depth: texture_depth_2d;
+
+[[block]]
+struct DeferredUniforms {
+    view_proj: mat4x4<f32>;
+    inv_view_proj: mat4x4<f32>;
+    eye_position: vec4<f32>;
+    far_depth: f32;
+};
+[[group(1), binding(0)]]
+var<uniform> uniforms: DeferredUniforms;
+
+struct Surface {
+    position: vec3<f32>;
+    normal: vec3<f32>;
+    albedo: vec3<f32>;
+    roughness: f32;
+    metallic: f32;
+};
+
+fn load_surface(coords: vec2<i32>, depth: f32) -> Surface {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let uv = (vec2<f32>(coords) + vec2<f32>(0.5, 0.5)) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = uniforms.inv_view_proj * ndc;
+    let material = textureLoad(t_material, coords, 0);
+
+    var surface: Surface;
+    surface.position = world.xyz / world.w;
+    surface.normal = textureLoad(t_normal, coords, 0).xyz;
+    surface.albedo = textureLoad(t_albedo, coords, 0).rgb;
+    surface.roughness = material.r;
+    surface.metallic = material.g;
+    return surface;
+}
+
+// Lambert diffuse plus Blinn-Phong specular, with the exponent derived from roughness
+fn shade(surface: Surface, light_direction: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let view_direction = normalize(uniforms.eye_position.xyz - surface.position);
+    let half_direction = normalize(light_direction + view_direction);
+    let n_dot_l = max(dot(surface.normal, light_direction), 0.0);
+
+    let r2 = surface.roughness * surface.roughness;
+    let shininess = clamp(2.0 / max(r2 * r2, 0.0001) - 2.0, 1.0, 256.0);
+    let f0 = mix(vec3<f32>(0.04, 0.04, 0.04), surface.albedo, vec3<f32>(surface.metallic));
+    let specular = f0 * pow(max(dot(surface.normal, half_direction), 0.0), shininess);
+    let diffuse = surface.albedo * (1.0 - surface.metallic);
+
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
@@ -0,0 +1,52 @@
+// Vertex shader
+// One cube per point light, scaled to its radius and blended additively
+// The G-buffer bindings, DeferredUniforms, load_surface and shade come from deferred_common.wgsl
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+};
+struct LightInput {
+    [[location(5)]] position_radius: vec4<f32>;
+    [[location(6)]] color_intensity: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] position_radius: vec4<f32>;
+    [[location(1)]] color_intensity: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn main_vs(model: VertexInput, light: LightInput) -> VertexOutput {
+    let world_position = light.position_radius.xyz + model.position * light.position_radius.w;
+    var out: VertexOutput;
+    out.clip_position = uniforms.view_proj * vec4<f32>(world_position, 1.0);
+    out.position_radius = light.position_radius;
+    out.color_intensity = light.color_intensity;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, coords, 0);
+    if (depth == uniforms.far_depth) {
+        discard;
+    }
+
+    let surface = load_surface(coords, depth);
+    let to_light = in.position_radius.xyz - surface.position;
+    let light_distance = length(to_light);
+    let radius = in.position_radius.w;
+    // Windowed inverse square falloff, reaching zero exactly at the radius
+    let window = clamp(1.0 - pow(light_distance / radius, 4.0), 0.0, 1.0);
+    let attenuation = window * window / (light_distance * light_distance + 1.0);
+    if (attenuation <= 0.0) {
+        discard;
+    }
+
+    let radiance = in.color_intensity.rgb * in.color_intensity.a * attenuation;
+    return vec4<f32>(shade(surface, to_light / light_distance, radiance), 0.0);
+}
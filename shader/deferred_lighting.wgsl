@@ -0,0 +1,28 @@
+// Fragment shader
+// Full-screen pass: ambient, occlusion and the directional light
+// The G-buffer bindings, DeferredUniforms, load_surface and shade come from deferred_common.wgsl
+// VertexOutput and main_vs come from fullscreen.wgsl
+
+[[group(2), binding(0)]]
+var t_ssao: texture_2d<f32>;
+[[group(2), binding(1)]]
+var s_ssao: sampler;
+
+let AMBIENT_STRENGTH: f32 = 0.3;
+let LIGHT_DIRECTION: vec3<f32> = vec3<f32>(0.3713907, 0.557086, 0.7427813);
+
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, coords, 0);
+    // Leave the clear colour where nothing was drawn
+    if (depth == uniforms.far_depth) {
+        discard;
+    }
+
+    let surface = load_surface(coords, depth);
+    let occlusion = textureLoad(t_ssao, coords, 0).r;
+    let ambient = surface.albedo * AMBIENT_STRENGTH * occlusion;
+    let radiance = vec3<f32>(1.0 - AMBIENT_STRENGTH, 1.0 - AMBIENT_STRENGTH, 1.0 - AMBIENT_STRENGTH);
+    return vec4<f32>(ambient + shade(surface, LIGHT_DIRECTION, radiance), 1.0);
+}
@@ -1,26 +1,22 @@
 // Vertex shader
 
-[[block]]
-struct Uniforms {
-    view_proj: mat4x4<f32>;
-};
+// CameraUniform comes from camera.wgsl
 [[group(1), binding(0)]]
-var<uniform> uniforms: Uniforms;
+var<uniform> camera: CameraUniform;
 
 struct VertexInput {
     [[location(0)]] position: vec3<f32>;
     [[location(1)]] tex_coords: vec2<f32>;
+    [[location(2)]] normal: vec3<f32>;
 };
-struct InstanceInput {
-    [[location(5)]] model_matrix_0: vec4<f32>;
-    [[location(6)]] model_matrix_1: vec4<f32>;
-    [[location(7)]] model_matrix_2: vec4<f32>;
-    [[location(8)]] model_matrix_3: vec4<f32>;
-};
+// InstanceInput is generated from InstanceRaw in instance.rs, with the helpers in instance.wgsl
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] tex_coords: vec2<f32>;
+    [[location(1)]] world_normal: vec3<f32>;
+    [[location(2)]] world_position: vec3<f32>;
+    [[location(3)]] tint: vec4<f32>;
 };
 
 [[stage(vertex)]]
@@ -28,15 +24,14 @@ fn main_vs(
     model: VertexInput,
     instance: InstanceInput,
 ) -> VertexOutput {
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
+    let model_matrix = instance_model_matrix(instance);
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = uniforms.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.tint = instance.tint;
+    out.world_normal = instance_world_normal(instance, model.normal);
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
     return out;
 }
 
@@ -47,7 +42,137 @@ var t_diffuse: texture_2d<f32>;
 [[group(0), binding(1)]]
 var s_diffuse: sampler;
 
+[[block]]
+struct Material {
+    tint: vec4<f32>;
+    roughness: f32;
+    metallic: f32;
+    alpha_cutoff: f32;
+};
+[[group(0), binding(2)]]
+var<uniform> material: Material;
+
+[[group(2), binding(0)]]
+var t_ssao: texture_2d<f32>;
+[[group(2), binding(1)]]
+var s_ssao: sampler;
+
+// MAX_LIGHTS_PER_CLUSTER, ClusterUniforms, PointLight, Lights and Clusters come from cluster_common.wgsl
+[[group(3), binding(0)]]
+var<uniform> cluster_uniforms: ClusterUniforms;
+[[group(3), binding(1)]]
+var<storage> lights: [[access(read)]] Lights;
+[[group(3), binding(2)]]
+var<storage> clusters: [[access(read)]] Clusters;
+
+let AMBIENT_STRENGTH: f32 = 0.3;
+let LIGHT_DIRECTION: vec3<f32> = vec3<f32>(0.3713907, 0.557086, 0.7427813);
+
+// Lambert diffuse plus Blinn-Phong specular, with the exponent derived from roughness
+fn shade(
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    view_direction: vec3<f32>,
+    light_direction: vec3<f32>,
+    radiance: vec3<f32>,
+) -> vec3<f32> {
+    let half_direction = normalize(light_direction + view_direction);
+    let n_dot_l = max(dot(normal, light_direction), 0.0);
+
+    let r2 = material.roughness * material.roughness;
+    let shininess = clamp(2.0 / max(r2 * r2, 0.0001) - 2.0, 1.0, 256.0);
+    let f0 = mix(vec3<f32>(0.04, 0.04, 0.04), albedo, vec3<f32>(material.metallic));
+    let specular = f0 * pow(max(dot(normal, half_direction), 0.0), shininess);
+    let diffuse = albedo * (1.0 - material.metallic);
+
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+fn cluster_index(frag_coord: vec2<f32>, view_depth: f32) -> u32 {
+    let grid = cluster_uniforms.grid;
+    let tile = vec2<u32>(frag_coord / cluster_uniforms.screen_size * vec2<f32>(f32(grid.x), f32(grid.y)));
+    let depth_range = log(cluster_uniforms.zfar / cluster_uniforms.znear);
+    let slice = u32(max(log(view_depth / cluster_uniforms.znear) / depth_range * f32(grid.z), 0.0));
+    let x = min(tile.x, grid.x - 1u);
+    let y = min(tile.y, grid.y - 1u);
+    let z = min(slice, grid.z - 1u);
+    return x + grid.x * (y + grid.y * z);
+}
+
+// Sun, ambient and every light binned into the fragment's cluster
+fn light_fragment(
+    albedo: vec3<f32>,
+    frag_coord: vec2<f32>,
+    world_position: vec3<f32>,
+    world_normal: vec3<f32>,
+) -> vec3<f32> {
+    // Clamped so views without their own occlusion can bind a 1x1 white texture
+    let ssao_coords = min(vec2<i32>(frag_coord), textureDimensions(t_ssao) - vec2<i32>(1, 1));
+    let occlusion = textureLoad(t_ssao, ssao_coords, 0).r;
+    let normal = normalize(world_normal);
+    let view_direction = normalize(camera.eye_position.xyz - world_position);
+
+    let ambient = albedo * AMBIENT_STRENGTH * occlusion;
+    let sun = vec3<f32>(1.0 - AMBIENT_STRENGTH, 1.0 - AMBIENT_STRENGTH, 1.0 - AMBIENT_STRENGTH);
+    var lighting: vec3<f32> = ambient + shade(albedo, normal, view_direction, LIGHT_DIRECTION, sun);
+
+    // Only the lights binned into this fragment's cluster can reach it
+    let view_depth = -(camera.view * vec4<f32>(world_position, 1.0)).z;
+    let base = cluster_index(frag_coord, view_depth) * (MAX_LIGHTS_PER_CLUSTER + 1u);
+    let count = min(clusters.data[base], MAX_LIGHTS_PER_CLUSTER);
+    for (var i: u32 = 0u; i < count; i = i + 1u) {
+        let light_index = clusters.data[base + 1u + i];
+        let light = lights.data[light_index];
+        let to_light = light.position - world_position;
+        let light_distance = length(to_light);
+        // Windowed inverse square falloff, reaching zero exactly at the radius
+        let window = clamp(1.0 - pow(light_distance / light.radius, 4.0), 0.0, 1.0);
+        let attenuation = window * window / (light_distance * light_distance + 1.0);
+        let radiance = light.color * light.intensity * attenuation;
+        lighting = lighting + shade(albedo, normal, view_direction, to_light / light_distance, radiance);
+    }
+
+    return lighting;
+}
+
 [[stage(fragment)]]
 fn main_fs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.tint * in.tint;
+    if (color.a < material.alpha_cutoff) {
+        discard;
+    }
+    let lighting = light_fragment(color.rgb, in.clip_position.xy, in.world_position, in.world_normal);
+    return vec4<f32>(lighting, color.a);
+}
+
+struct OitOutput {
+    [[location(0)]] accum: vec4<f32>;
+    [[location(1)]] revealage: f32;
+};
+
+// Weighted blended order-independent transparency (McGuire and Bavoil 2013).
+// Premultiplied colour is summed with a depth weight, revealage is the product of (1 - alpha).
+[[stage(fragment)]]
+fn oit_fs(in: VertexOutput) -> OitOutput {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.tint * in.tint;
+    let lighting = light_fragment(color.rgb, in.clip_position.xy, in.world_position, in.world_normal);
+
+    // Weighted by view distance (equation 9 of the paper), so it holds with reverse-Z as well
+    let view_depth = -(camera.view * vec4<f32>(in.world_position, 1.0)).z;
+    let falloff = 0.00001 + pow(view_depth / 5.0, 2.0) + pow(view_depth / 200.0, 6.0);
+    let weight = color.a * clamp(10.0 / falloff, 0.01, 3000.0);
+
+    var out: OitOutput;
+    out.accum = vec4<f32>(lighting * color.a, color.a) * weight;
+    out.revealage = color.a;
+    return out;
+}
+
+// Alpha tested depth only output for the prepass, so cutouts don't block what's behind them
+[[stage(fragment)]]
+fn depth_fs(in: VertexOutput) {
+    let alpha = textureSample(t_diffuse, s_diffuse, in.tex_coords).a * material.tint.a * in.tint.a;
+    if (alpha < material.alpha_cutoff) {
+        discard;
+    }
+}
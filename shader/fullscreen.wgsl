@@ -0,0 +1,19 @@
+// Vertex shader
+// The full-screen triangle shared by every post and composite pass, prepended to their fragment
+// shaders by pipeline::fullscreen_wgsl. Drawn with three vertices and no vertex buffers.
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+// Generates a single triangle that covers the whole target or viewport from the vertex index
+[[stage(vertex)]]
+fn main_vs([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.uv = uv;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
@@ -0,0 +1,68 @@
+// Fragment shader
+// VertexOutput and main_vs come from fullscreen.wgsl
+
+let FXAA_SPAN_MAX: f32 = 8.0;
+let FXAA_REDUCE_MUL: f32 = 0.125;
+let FXAA_REDUCE_MIN: f32 = 0.0078125;
+
+[[group(0), binding(0)]]
+var t_input: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_input: sampler;
+
+// The input is sampled as linear colour, FXAA wants perceptual luma
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+
+    let rgb_nw = textureSample(t_input, s_input, in.uv + vec2<f32>(-1.0, -1.0) * texel).rgb;
+    let rgb_ne = textureSample(t_input, s_input, in.uv + vec2<f32>(1.0, -1.0) * texel).rgb;
+    let rgb_sw = textureSample(t_input, s_input, in.uv + vec2<f32>(-1.0, 1.0) * texel).rgb;
+    let rgb_se = textureSample(t_input, s_input, in.uv + vec2<f32>(1.0, 1.0) * texel).rgb;
+    let rgb_m = textureSample(t_input, s_input, in.uv).rgb;
+
+    let luma_nw = luma(rgb_nw);
+    let luma_ne = luma(rgb_ne);
+    let luma_sw = luma(rgb_sw);
+    let luma_se = luma(rgb_se);
+    let luma_m = luma(rgb_m);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // The blur direction runs along the edge, perpendicular to the luma gradient
+    let dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce = max(
+        (luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL,
+        FXAA_REDUCE_MIN,
+    );
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    let span = clamp(
+        dir * rcp_dir_min,
+        vec2<f32>(-FXAA_SPAN_MAX, -FXAA_SPAN_MAX),
+        vec2<f32>(FXAA_SPAN_MAX, FXAA_SPAN_MAX),
+    ) * texel;
+
+    let rgb_a = 0.5 * (
+        textureSample(t_input, s_input, in.uv + span * (1.0 / 3.0 - 0.5)).rgb +
+        textureSample(t_input, s_input, in.uv + span * (2.0 / 3.0 - 0.5)).rgb
+    );
+    let rgb_b = rgb_a * 0.5 + 0.25 * (
+        textureSample(t_input, s_input, in.uv - span * 0.5).rgb +
+        textureSample(t_input, s_input, in.uv + span * 0.5).rgb
+    );
+
+    // The wider blur overshot the local contrast, fall back to the narrow one
+    let luma_b = luma(rgb_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(rgb_a, 1.0);
+    }
+    return vec4<f32>(rgb_b, 1.0);
+}
@@ -0,0 +1,71 @@
+// Vertex shader
+
+// CameraUniform comes from camera.wgsl
+[[group(1), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] tex_coords: vec2<f32>;
+    [[location(2)]] normal: vec3<f32>;
+};
+// InstanceInput is generated from InstanceRaw in instance.rs, with the helpers in instance.wgsl
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+    [[location(1)]] world_normal: vec3<f32>;
+    [[location(2)]] tint: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn main_vs(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = instance_model_matrix(instance);
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.tint = instance.tint;
+    out.world_normal = instance_world_normal(instance, model.normal);
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+// Fragment shader
+// Writes the surface attributes the deferred lighting passes need
+
+[[group(0), binding(0)]]
+var t_diffuse: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_diffuse: sampler;
+
+[[block]]
+struct Material {
+    tint: vec4<f32>;
+    roughness: f32;
+    metallic: f32;
+    alpha_cutoff: f32;
+};
+[[group(0), binding(2)]]
+var<uniform> material: Material;
+
+struct GBufferOutput {
+    [[location(0)]] albedo: vec4<f32>;
+    [[location(1)]] normal: vec4<f32>;
+    [[location(2)]] material: vec4<f32>;
+};
+
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> GBufferOutput {
+    let albedo = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.tint * in.tint;
+    if (albedo.a < material.alpha_cutoff) {
+        discard;
+    }
+
+    var out: GBufferOutput;
+    out.albedo = albedo;
+    out.normal = vec4<f32>(normalize(in.world_normal), 0.0);
+    out.material = vec4<f32>(material.roughness, material.metallic, 0.0, 1.0);
+    return out;
+}
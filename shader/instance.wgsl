@@ -0,0 +1,21 @@
+// Helpers for the InstanceInput generated from InstanceRaw in instance.rs, appended after it
+
+fn instance_model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+// Scene nodes can scale each axis differently, which would tilt a normal taken through the
+// model matrix. Dividing by the squared axis scales first makes it the inverse transpose.
+fn instance_world_normal(instance: InstanceInput, normal: vec3<f32>) -> vec3<f32> {
+    let scale_squared = vec3<f32>(
+        dot(instance.model_matrix_0.xyz, instance.model_matrix_0.xyz),
+        dot(instance.model_matrix_1.xyz, instance.model_matrix_1.xyz),
+        dot(instance.model_matrix_2.xyz, instance.model_matrix_2.xyz),
+    );
+    return (instance_model_matrix(instance) * vec4<f32>(normal / scale_squared, 0.0)).xyz;
+}
@@ -0,0 +1,24 @@
+// Fragment shader
+// VertexOutput and main_vs come from fullscreen.wgsl
+
+[[group(0), binding(0)]]
+var t_accum: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_accum: sampler;
+[[group(1), binding(0)]]
+var t_revealage: texture_2d<f32>;
+[[group(1), binding(1)]]
+var s_revealage: sampler;
+
+// Resolves the weighted average colour, blended over the opaque scene by the total coverage
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let revealage = textureLoad(t_revealage, coords, 0).r;
+    if (revealage >= 0.999) {
+        discard;
+    }
+    let accum = textureLoad(t_accum, coords, 0);
+    let average = accum.rgb / max(accum.a, 0.00001);
+    return vec4<f32>(average, 1.0 - revealage);
+}
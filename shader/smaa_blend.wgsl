@@ -0,0 +1,47 @@
+// Fragment shader
+// SMAA pass 3: neighbourhood blending with the weights from pass 2
+// VertexOutput and main_vs come from fullscreen.wgsl
+
+[[group(0), binding(0)]]
+var t_input: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_input: sampler;
+
+[[group(1), binding(0)]]
+var t_weights: texture_2d<f32>;
+[[group(1), binding(1)]]
+var s_weights: sampler;
+
+fn color_at(coords: vec2<i32>) -> vec3<f32> {
+    let size = textureDimensions(t_input);
+    return textureLoad(t_input, clamp(coords, vec2<i32>(0, 0), size - vec2<i32>(1, 1)), 0).rgb;
+}
+
+fn weights_at(coords: vec2<i32>) -> vec4<f32> {
+    let size = textureDimensions(t_weights);
+    return textureLoad(t_weights, clamp(coords, vec2<i32>(0, 0), size - vec2<i32>(1, 1)), 0);
+}
+
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let here = weights_at(coords);
+    let w_top = here.r;
+    let w_left = here.b;
+    let w_bottom = weights_at(coords + vec2<i32>(0, 1)).g;
+    let w_right = weights_at(coords + vec2<i32>(1, 0)).a;
+
+    let color = color_at(coords);
+    let total = w_top + w_left + w_bottom + w_right;
+    if (total <= 0.0) {
+        return vec4<f32>(color, 1.0);
+    }
+
+    let blended =
+        color_at(coords - vec2<i32>(0, 1)) * w_top +
+        color_at(coords - vec2<i32>(1, 0)) * w_left +
+        color_at(coords + vec2<i32>(0, 1)) * w_bottom +
+        color_at(coords + vec2<i32>(1, 0)) * w_right;
+    let scale = max(total, 1.0);
+    return vec4<f32>(color * (1.0 - total / scale) + blended / scale, 1.0);
+}
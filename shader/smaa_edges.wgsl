@@ -0,0 +1,28 @@
+// Fragment shader
+// SMAA pass 1: luma edge detection. r flags an edge on the left of the pixel, g on the top.
+// VertexOutput and main_vs come from fullscreen.wgsl
+
+let SMAA_THRESHOLD: f32 = 0.1;
+
+[[group(0), binding(0)]]
+var t_input: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_input: sampler;
+
+fn luma_at(coords: vec2<i32>) -> f32 {
+    let c = max(coords, vec2<i32>(0, 0));
+    let color = textureLoad(t_input, c, 0).rgb;
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let l = luma_at(coords);
+    let delta = abs(vec2<f32>(
+        l - luma_at(coords - vec2<i32>(1, 0)),
+        l - luma_at(coords - vec2<i32>(0, 1)),
+    ));
+    let edges = step(vec2<f32>(SMAA_THRESHOLD, SMAA_THRESHOLD), delta);
+    return vec4<f32>(edges, 0.0, 0.0);
+}
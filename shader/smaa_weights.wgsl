@@ -0,0 +1,92 @@
+// Fragment shader
+// SMAA pass 2: blending weights. Instead of the precomputed area texture the
+// silhouette is rebuilt from the crossing edges at both ends of the line and
+// its coverage is evaluated at the pixel centre.
+// r: this pixel takes its top neighbour, g: the top neighbour takes this pixel,
+// b: this pixel takes its left neighbour, a: the left neighbour takes this pixel.
+// VertexOutput and main_vs come from fullscreen.wgsl
+
+let SMAA_MAX_SEARCH: i32 = 16;
+
+[[group(0), binding(0)]]
+var t_edges: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_edges: sampler;
+
+fn edge(coords: vec2<i32>) -> vec2<f32> {
+    let size = textureDimensions(t_edges);
+    let c = clamp(coords, vec2<i32>(0, 0), size - vec2<i32>(1, 1));
+    return textureLoad(t_edges, c, 0).rg;
+}
+
+// a and b are the line heights at the two ends, pos is the distance of the
+// pixel centre from the first end and len the length of the line
+fn area(a: f32, b: f32, pos: f32, len: f32) -> f32 {
+    // Z shape, a single line runs from one end to the other
+    if (a * b < 0.0) {
+        return mix(a, b, pos / len);
+    }
+    // L and U shapes, each end bends towards the middle of the line
+    let half = len * 0.5;
+    if (pos < half) {
+        return a * (1.0 - pos / half);
+    }
+    return b * (pos / half - 1.0);
+}
+
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let e = edge(coords);
+    var weights: vec4<f32> = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+
+    if (e.g > 0.0) {
+        var left: i32 = 0;
+        for (var i: i32 = 1; i < SMAA_MAX_SEARCH; i = i + 1) {
+            if (edge(coords - vec2<i32>(i, 0)).g == 0.0) {
+                break;
+            }
+            left = i;
+        }
+        var right: i32 = 0;
+        for (var i: i32 = 1; i < SMAA_MAX_SEARCH; i = i + 1) {
+            if (edge(coords + vec2<i32>(i, 0)).g == 0.0) {
+                break;
+            }
+            right = i;
+        }
+        let x_left = coords.x - left;
+        let x_right = coords.x + right + 1;
+        let a = 0.5 * (edge(vec2<i32>(x_left, coords.y - 1)).r - edge(vec2<i32>(x_left, coords.y)).r);
+        let b = 0.5 * (edge(vec2<i32>(x_right, coords.y - 1)).r - edge(vec2<i32>(x_right, coords.y)).r);
+        let h = area(a, b, f32(left) + 0.5, f32(left + right + 1));
+        weights.r = max(-h, 0.0);
+        weights.g = max(h, 0.0);
+    }
+
+    if (e.r > 0.0) {
+        var up: i32 = 0;
+        for (var i: i32 = 1; i < SMAA_MAX_SEARCH; i = i + 1) {
+            if (edge(coords - vec2<i32>(0, i)).r == 0.0) {
+                break;
+            }
+            up = i;
+        }
+        var down: i32 = 0;
+        for (var i: i32 = 1; i < SMAA_MAX_SEARCH; i = i + 1) {
+            if (edge(coords + vec2<i32>(0, i)).r == 0.0) {
+                break;
+            }
+            down = i;
+        }
+        let y_top = coords.y - up;
+        let y_bottom = coords.y + down + 1;
+        let a = 0.5 * (edge(vec2<i32>(coords.x - 1, y_top)).g - edge(vec2<i32>(coords.x, y_top)).g);
+        let b = 0.5 * (edge(vec2<i32>(coords.x - 1, y_bottom)).g - edge(vec2<i32>(coords.x, y_bottom)).g);
+        let h = area(a, b, f32(up) + 0.5, f32(up + down + 1));
+        weights.b = max(-h, 0.0);
+        weights.a = max(h, 0.0);
+    }
+
+    return weights;
+}
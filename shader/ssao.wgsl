@@ -0,0 +1,92 @@
+// Fragment shader
+// Hemisphere sampled ambient occlusion, reconstructing view space from the depth buffer
+// VertexOutput and main_vs come from fullscreen.wgsl
+
+let SSAO_KERNEL_SIZE: i32 = 16;
+let SSAO_NOISE_SIZE: i32 = 4;
+
+[[block]]
+struct SsaoUniforms {
+    proj: mat4x4<f32>;
+    inv_proj: mat4x4<f32>;
+    kernel: array<vec4<f32>, 16>;
+    radius: f32;
+    bias: f32;
+    far_depth: f32;
+};
+
+[[group(0), binding(0)]]
+var t_depth: texture_depth_2d;
+[[group(0), binding(1)]]
+var t_noise: texture_2d<f32>;
+[[group(0), binding(2)]]
+var<uniform> uniforms: SsaoUniforms;
+
+fn clamp_coords(coords: vec2<i32>) -> vec2<i32> {
+    let size = textureDimensions(t_depth);
+    return clamp(coords, vec2<i32>(0, 0), size - vec2<i32>(1, 1));
+}
+
+fn view_position(coords: vec2<i32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let depth = textureLoad(t_depth, coords, 0);
+    let uv = (vec2<f32>(coords) + vec2<f32>(0.5, 0.5)) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let view = uniforms.inv_proj * ndc;
+    return view.xyz / view.w;
+}
+
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] f32 {
+    let coords = vec2<i32>(in.clip_position.xy);
+    // Nothing was drawn here, so there is nothing to occlude
+    if (textureLoad(t_depth, coords, 0) == uniforms.far_depth) {
+        return 1.0;
+    }
+
+    // Rebuild the normal from the neighbour with the smaller depth step on
+    // each axis, so silhouettes don't bend it towards the background
+    let position = view_position(coords);
+    let right = view_position(clamp_coords(coords + vec2<i32>(1, 0))) - position;
+    let left = position - view_position(clamp_coords(coords - vec2<i32>(1, 0)));
+    let down = view_position(clamp_coords(coords + vec2<i32>(0, 1))) - position;
+    let up = position - view_position(clamp_coords(coords - vec2<i32>(0, 1)));
+    var dx: vec3<f32> = right;
+    if (abs(left.z) < abs(right.z)) {
+        dx = left;
+    }
+    var dy: vec3<f32> = down;
+    if (abs(up.z) < abs(down.z)) {
+        dy = up;
+    }
+    var normal: vec3<f32> = normalize(cross(dy, dx));
+    if (dot(normal, position) > 0.0) {
+        normal = -normal;
+    }
+
+    // Gram-Schmidt the tiled random vector into a tangent frame around the normal
+    let noise = textureLoad(t_noise, coords % vec2<i32>(SSAO_NOISE_SIZE, SSAO_NOISE_SIZE), 0).xyz * 2.0 - 1.0;
+    let tangent = normalize(noise - normal * dot(noise, normal));
+    let bitangent = cross(normal, tangent);
+
+    let size = vec2<f32>(textureDimensions(t_depth));
+    var occlusion: f32 = 0.0;
+    for (var i: i32 = 0; i < SSAO_KERNEL_SIZE; i = i + 1) {
+        let k = uniforms.kernel[i];
+        let sample_position = position
+            + (tangent * k.x + bitangent * k.y + normal * k.z) * uniforms.radius;
+
+        let clip = uniforms.proj * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        let sample_depth = view_position(clamp_coords(vec2<i32>(uv * size))).z;
+
+        // Fade out occluders that are much further away than the sample radius
+        let range = clamp(uniforms.radius / abs(position.z - sample_depth), 0.0, 1.0);
+        if (sample_depth >= sample_position.z + uniforms.bias) {
+            occlusion = occlusion + range * range * (3.0 - 2.0 * range);
+        }
+    }
+
+    return 1.0 - occlusion / f32(SSAO_KERNEL_SIZE);
+}
@@ -0,0 +1,24 @@
+// Fragment shader
+// Box blur matching the noise tile, which removes the tiling pattern
+// VertexOutput and main_vs come from fullscreen.wgsl
+
+let SSAO_NOISE_SIZE: i32 = 4;
+
+[[group(0), binding(0)]]
+var t_ssao: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_ssao: sampler;
+
+[[stage(fragment)]]
+fn main_fs(in: VertexOutput) -> [[location(0)]] f32 {
+    let coords = vec2<i32>(in.clip_position.xy);
+    let size = textureDimensions(t_ssao);
+    var result: f32 = 0.0;
+    for (var y: i32 = -SSAO_NOISE_SIZE / 2; y < SSAO_NOISE_SIZE / 2; y = y + 1) {
+        for (var x: i32 = -SSAO_NOISE_SIZE / 2; x < SSAO_NOISE_SIZE / 2; x = x + 1) {
+            let c = clamp(coords + vec2<i32>(x, y), vec2<i32>(0, 0), size - vec2<i32>(1, 1));
+            result = result + textureLoad(t_ssao, c, 0).r;
+        }
+    }
+    return result / f32(SSAO_NOISE_SIZE * SSAO_NOISE_SIZE);
+}
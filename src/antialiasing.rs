@@ -0,0 +1,320 @@
+use crate::{bundle::Assets, pipeline, texture};
+
+pub const MSAA_SAMPLE_COUNT: u32 = 4;
+const SMAA_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// Screen-space anti-aliasing run on the final LDR image before presentation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostAntiAliasing {
+    None,
+    Fxaa,
+    Smaa,
+}
+
+impl PostAntiAliasing {
+    pub fn next(self) -> Self {
+        match self {
+            PostAntiAliasing::None => PostAntiAliasing::Fxaa,
+            PostAntiAliasing::Fxaa => PostAntiAliasing::Smaa,
+            PostAntiAliasing::Smaa => PostAntiAliasing::None,
+        }
+    }
+}
+
+/// Owns the scene colour targets and the post-process passes, so that
+/// multisampling and FXAA/SMAA can be switched independently at runtime.
+pub struct AntiAliasing {
+    pub mode: PostAntiAliasing,
+    pub sample_count: u32,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    fxaa_pipeline: wgpu::RenderPipeline,
+    smaa_edges_pipeline: wgpu::RenderPipeline,
+    smaa_weights_pipeline: wgpu::RenderPipeline,
+    smaa_blend_pipeline: wgpu::RenderPipeline,
+    scene_color: texture::Texture,
+    msaa_color: Option<texture::Texture>,
+    smaa_edges: texture::Texture,
+    smaa_weights: texture::Texture,
+    scene_bind_group: wgpu::BindGroup,
+    edges_bind_group: wgpu::BindGroup,
+    weights_bind_group: wgpu::BindGroup,
+}
+
+impl AntiAliasing {
+    pub fn new(
+        device: &wgpu::Device,
+        assets: &Assets,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        mode: PostAntiAliasing,
+        sample_count: u32,
+    ) -> Self {
+        let texture_bind_group_layout = pipeline::create_texture_bind_group_layout(
+            device,
+            wgpu::ShaderStage::FRAGMENT,
+            "post_texture_bind_group_layout",
+        );
+        let single_input_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Single Input Pipeline Layout"),
+            bind_group_layouts: &[&texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let double_input_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Double Input Pipeline Layout"),
+            bind_group_layouts: &[&texture_bind_group_layout, &texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = |label, name, built_in| {
+            device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(pipeline::fullscreen_wgsl(
+                    assets,
+                    &assets.shader(name, built_in),
+                )),
+                flags: wgpu::ShaderFlags::default(),
+            })
+        };
+        let fxaa_module = shader(
+            "FXAA Shader",
+            "fxaa.wgsl",
+            include_str!("../shader/fxaa.wgsl"),
+        );
+        let smaa_edges_module = shader(
+            "SMAA Edges Shader",
+            "smaa_edges.wgsl",
+            include_str!("../shader/smaa_edges.wgsl"),
+        );
+        let smaa_weights_module = shader(
+            "SMAA Weights Shader",
+            "smaa_weights.wgsl",
+            include_str!("../shader/smaa_weights.wgsl"),
+        );
+        let smaa_blend_module = shader(
+            "SMAA Blend Shader",
+            "smaa_blend.wgsl",
+            include_str!("../shader/smaa_blend.wgsl"),
+        );
+
+        let fxaa_pipeline = pipeline::create_fullscreen_pipeline(
+            device,
+            &single_input_layout,
+            sc_desc.format,
+            &fxaa_module,
+            "FXAA Pipeline",
+        );
+        let smaa_edges_pipeline = pipeline::create_fullscreen_pipeline(
+            device,
+            &single_input_layout,
+            SMAA_FORMAT,
+            &smaa_edges_module,
+            "SMAA Edges Pipeline",
+        );
+        let smaa_weights_pipeline = pipeline::create_fullscreen_pipeline(
+            device,
+            &single_input_layout,
+            SMAA_FORMAT,
+            &smaa_weights_module,
+            "SMAA Weights Pipeline",
+        );
+        let smaa_blend_pipeline = pipeline::create_fullscreen_pipeline(
+            device,
+            &double_input_layout,
+            sc_desc.format,
+            &smaa_blend_module,
+            "SMAA Blend Pipeline",
+        );
+
+        let (scene_color, msaa_color, smaa_edges, smaa_weights) =
+            Self::create_targets(device, sc_desc, sample_count);
+        let (scene_bind_group, edges_bind_group, weights_bind_group) = Self::create_bind_groups(
+            device,
+            &texture_bind_group_layout,
+            &scene_color,
+            &smaa_edges,
+            &smaa_weights,
+        );
+
+        Self {
+            mode,
+            sample_count,
+            texture_bind_group_layout,
+            fxaa_pipeline,
+            smaa_edges_pipeline,
+            smaa_weights_pipeline,
+            smaa_blend_pipeline,
+            scene_color,
+            msaa_color,
+            smaa_edges,
+            smaa_weights,
+            scene_bind_group,
+            edges_bind_group,
+            weights_bind_group,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
+    ) -> (
+        texture::Texture,
+        Option<texture::Texture>,
+        texture::Texture,
+        texture::Texture,
+    ) {
+        let scene_color = texture::Texture::create_render_target(
+            device,
+            sc_desc,
+            sc_desc.format,
+            1,
+            "scene_color",
+        );
+        let msaa_color = if sample_count > 1 {
+            Some(texture::Texture::create_render_target(
+                device,
+                sc_desc,
+                sc_desc.format,
+                sample_count,
+                "msaa_color",
+            ))
+        } else {
+            None
+        };
+        let smaa_edges =
+            texture::Texture::create_render_target(device, sc_desc, SMAA_FORMAT, 1, "smaa_edges");
+        let smaa_weights =
+            texture::Texture::create_render_target(device, sc_desc, SMAA_FORMAT, 1, "smaa_weights");
+        (scene_color, msaa_color, smaa_edges, smaa_weights)
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        scene_color: &texture::Texture,
+        smaa_edges: &texture::Texture,
+        smaa_weights: &texture::Texture,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup, wgpu::BindGroup) {
+        (
+            pipeline::create_texture_bind_group(device, layout, scene_color, "scene_bind_group"),
+            pipeline::create_texture_bind_group(device, layout, smaa_edges, "edges_bind_group"),
+            pipeline::create_texture_bind_group(device, layout, smaa_weights, "weights_bind_group"),
+        )
+    }
+
+    ///Fn: Resize: Recreates every screen sized target
+    ///Params: Also used to apply a new sample_count
+    pub fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) {
+        let (scene_color, msaa_color, smaa_edges, smaa_weights) =
+            Self::create_targets(device, sc_desc, self.sample_count);
+        let (scene_bind_group, edges_bind_group, weights_bind_group) = Self::create_bind_groups(
+            device,
+            &self.texture_bind_group_layout,
+            &scene_color,
+            &smaa_edges,
+            &smaa_weights,
+        );
+        self.scene_color = scene_color;
+        self.msaa_color = msaa_color;
+        self.smaa_edges = smaa_edges;
+        self.smaa_weights = smaa_weights;
+        self.scene_bind_group = scene_bind_group;
+        self.edges_bind_group = edges_bind_group;
+        self.weights_bind_group = weights_bind_group;
+    }
+
+    ///Fn: Scene Attachment: Where the scene pass should draw to
+    ///Params: frame_view is used directly when there is no post-process pass
+    pub fn scene_attachment<'a>(
+        &'a self,
+        frame_view: &'a wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'a> {
+        let target = self.scene_view(frame_view);
+        match &self.msaa_color {
+            Some(msaa_color) => wgpu::RenderPassColorAttachment {
+                view: &msaa_color.view,
+                resolve_target: Some(target),
+                ops: wgpu::Operations { load, store: true },
+            },
+            None => wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            },
+        }
+    }
+
+    ///Fn: Scene View: The single sampled view the scene is resolved into
+    ///Params: frame_view is used directly when there is no post-process pass
+    pub fn scene_view<'a>(&'a self, frame_view: &'a wgpu::TextureView) -> &'a wgpu::TextureView {
+        match self.mode {
+            PostAntiAliasing::None => frame_view,
+            _ => &self.scene_color.view,
+        }
+    }
+
+    ///Fn: Render: Runs the selected post-process passes into frame_view
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, frame_view: &wgpu::TextureView) {
+        match self.mode {
+            PostAntiAliasing::None => {}
+            PostAntiAliasing::Fxaa => {
+                Self::fullscreen_pass(
+                    encoder,
+                    "FXAA Pass",
+                    frame_view,
+                    &self.fxaa_pipeline,
+                    &[&self.scene_bind_group],
+                );
+            }
+            PostAntiAliasing::Smaa => {
+                Self::fullscreen_pass(
+                    encoder,
+                    "SMAA Edges Pass",
+                    &self.smaa_edges.view,
+                    &self.smaa_edges_pipeline,
+                    &[&self.scene_bind_group],
+                );
+                Self::fullscreen_pass(
+                    encoder,
+                    "SMAA Weights Pass",
+                    &self.smaa_weights.view,
+                    &self.smaa_weights_pipeline,
+                    &[&self.edges_bind_group],
+                );
+                Self::fullscreen_pass(
+                    encoder,
+                    "SMAA Blend Pass",
+                    frame_view,
+                    &self.smaa_blend_pipeline,
+                    &[&self.scene_bind_group, &self.weights_bind_group],
+                );
+            }
+        }
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        view: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        bind_groups: &[&wgpu::BindGroup],
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            render_pass.set_bind_group(index as u32, bind_group, &[]);
+        }
+        render_pass.draw(0..3, 0..1);
+    }
+}
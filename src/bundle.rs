@@ -0,0 +1,561 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    io::{BufWriter, Write},
+    mem,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use memmap2::Mmap;
+
+/// Starts every bundle
+const MAGIC: [u8; 8] = *b"ASHENPAK";
+/// Bumped whenever the layout changes
+pub const BUNDLE_VERSION: u32 = 1;
+/// Where the bundle subcommand writes unless given --output
+pub const BUNDLE_PATH: &str = "ashen.bundle";
+/// What the bundle subcommand packs when it isn't given any directories
+pub const BUNDLE_DIRECTORIES: &[&str] = &["assets", "scenes", SHADER_PATH];
+/// Where WGSL sources are read from, relative to the working directory
+pub const SHADER_PATH: &str = "shader";
+/// Entry data starts on this boundary, so it can be cast in place to vertex, index or uniform data
+const DATA_ALIGNMENT: usize = 16;
+
+/// The start of the file, followed by the table of contents, the names and the data
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    entry_count: u32,
+}
+
+/// One file in the table of contents. Offsets are from the start of the bundle.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TocEntry {
+    name_offset: u64,
+    offset: u64,
+    size: u64,
+    name_len: u32,
+    kind: u32,
+}
+
+/// What a packed file holds, picked from its extension when the bundle is built
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    /// An image file, kept in its compressed format and decoded when it's loaded
+    Texture,
+    /// WGSL source
+    Shader,
+    /// A RON scene file
+    Scene,
+    /// Vertex, index and submesh data written by MeshData::to_bytes
+    Mesh,
+    Other,
+}
+
+impl EntryKind {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("png") | Some("jpg") | Some("jpeg") => EntryKind::Texture,
+            Some("wgsl") => EntryKind::Shader,
+            Some("ron") => EntryKind::Scene,
+            Some("mesh") => EntryKind::Mesh,
+            _ => EntryKind::Other,
+        }
+    }
+
+    fn from_u32(kind: u32) -> Self {
+        match kind {
+            0 => EntryKind::Texture,
+            1 => EntryKind::Shader,
+            2 => EntryKind::Scene,
+            4 => EntryKind::Mesh,
+            _ => EntryKind::Other,
+        }
+    }
+
+    fn to_u32(self) -> u32 {
+        match self {
+            EntryKind::Texture => 0,
+            EntryKind::Shader => 1,
+            EntryKind::Scene => 2,
+            EntryKind::Other => 3,
+            EntryKind::Mesh => 4,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Entry {
+    pub kind: EntryKind,
+    offset: usize,
+    size: usize,
+}
+
+impl Entry {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// A bundle mapped into memory. Only the table of contents is read when it's opened,
+/// the data of each entry is paged in from the file as it's first touched.
+pub struct Bundle {
+    path: PathBuf,
+    map: Mmap,
+    entries: HashMap<String, Entry>,
+}
+
+impl Bundle {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            fs::File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+        // Safe as long as nothing rewrites the bundle while it's open
+        let map = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Couldn't map {}", path.display()))?;
+
+        let header_size = mem::size_of::<Header>();
+        ensure!(map.len() >= header_size, "{} is too short", path.display());
+        let header: &Header = bytemuck::from_bytes(&map[..header_size]);
+        ensure!(header.magic == MAGIC, "{} isn't a bundle", path.display());
+        ensure!(
+            header.version == BUNDLE_VERSION,
+            "{} is a version {} bundle, this build reads version {}",
+            path.display(),
+            header.version,
+            BUNDLE_VERSION
+        );
+
+        let toc_end = header_size + header.entry_count as usize * mem::size_of::<TocEntry>();
+        ensure!(
+            map.len() >= toc_end,
+            "{}'s table of contents is cut short",
+            path.display()
+        );
+        // The map is page aligned and the header a multiple of 8 bytes, so the table can be read in place
+        let toc: &[TocEntry] = bytemuck::cast_slice(&map[header_size..toc_end]);
+        let mut entries = HashMap::with_capacity(toc.len());
+        for toc_entry in toc {
+            let name = slice(&map, toc_entry.name_offset, toc_entry.name_len as u64)
+                .and_then(|name| std::str::from_utf8(name).ok());
+            let name = match name {
+                Some(name) => name,
+                None => bail!("{} has an entry with a broken name", path.display()),
+            };
+            ensure!(
+                slice(&map, toc_entry.offset, toc_entry.size).is_some(),
+                "{} in {} runs past the end of the file",
+                name,
+                path.display()
+            );
+            entries.insert(
+                name.to_string(),
+                Entry {
+                    kind: EntryKind::from_u32(toc_entry.kind),
+                    offset: toc_entry.offset as usize,
+                    size: toc_entry.size as usize,
+                },
+            );
+        }
+        log::info!("Opened {} with {} entries", path.display(), entries.len());
+        Ok(Self {
+            path: path.to_path_buf(),
+            map,
+            entries,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Entry)> {
+        self.entries
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry))
+    }
+
+    ///Fn: Get: The packed file with this name, a slice of the mapped bundle
+    ///Params: Names are the paths the files were packed from, with / separators, like assets/test.png
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        let entry = self.entries.get(name)?;
+        Some(&self.map[entry.offset..entry.offset + entry.size])
+    }
+}
+
+///Fn: Slice: The bytes at offset, None if they'd run past the end
+fn slice(bytes: &[u8], offset: u64, size: u64) -> Option<&[u8]> {
+    let end = offset.checked_add(size)?;
+    bytes.get(offset as usize..end as usize)
+}
+
+///Fn: Build: Packs every file under the directories into a bundle, along with generated entries
+///Params: generated pairs entry names with their data, a file packed under the same name replaces it.
+///Params: Returns the number of entries packed. The bundle is written beside output and renamed over
+///Params: it once complete, so a failed build leaves any old bundle as it was, even one that's open.
+pub fn build(
+    directories: &[impl AsRef<Path>],
+    generated: &[(String, Vec<u8>)],
+    output: impl AsRef<Path>,
+) -> Result<usize> {
+    let output = output.as_ref();
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let packed = write(directories, generated, &partial).and_then(|count| {
+        fs::rename(&partial, output).with_context(|| {
+            format!(
+                "Couldn't move {} to {}",
+                partial.display(),
+                output.display()
+            )
+        })?;
+        Ok(count)
+    });
+    if packed.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    packed
+}
+
+/// Where an entry's data comes from when the bundle is written
+enum Source<'a> {
+    File(PathBuf),
+    Generated(&'a [u8]),
+}
+
+fn write(
+    directories: &[impl AsRef<Path>],
+    generated: &[(String, Vec<u8>)],
+    output: &Path,
+) -> Result<usize> {
+    let mut files = Vec::new();
+    for directory in directories {
+        collect_files(directory.as_ref(), &mut files)?;
+    }
+    let mut sources = files
+        .into_iter()
+        .map(|path| {
+            let name = path.to_str().with_context(|| {
+                format!("{} isn't valid UTF-8, which bundles need", path.display())
+            })?;
+            Ok((name.replace('\\', "/"), Source::File(path)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for (name, data) in generated {
+        if !sources.iter().any(|(packed, _)| packed == name) {
+            sources.push((name.clone(), Source::Generated(data)));
+        }
+    }
+    // Sorted so the same files always give the same bundle
+    sources.sort_by(|(a, _), (b, _)| a.cmp(b));
+    sources.dedup_by(|(a, _), (b, _)| a == b);
+
+    let header_size = mem::size_of::<Header>();
+    let names_offset = header_size + sources.len() * mem::size_of::<TocEntry>();
+    let mut toc = Vec::with_capacity(sources.len());
+    let mut name_offset = names_offset;
+    let mut data_offset =
+        align(names_offset + sources.iter().map(|(name, _)| name.len()).sum::<usize>());
+    for (name, source) in &sources {
+        let size = match source {
+            Source::File(path) => fs::metadata(path)
+                .with_context(|| format!("Couldn't read {}", path.display()))?
+                .len() as usize,
+            Source::Generated(data) => data.len(),
+        };
+        toc.push(TocEntry {
+            name_offset: name_offset as u64,
+            offset: data_offset as u64,
+            size: size as u64,
+            name_len: name.len() as u32,
+            kind: EntryKind::from_path(Path::new(name)).to_u32(),
+        });
+        name_offset += name.len();
+        data_offset = align(data_offset + size);
+    }
+
+    let file = fs::File::create(output)
+        .with_context(|| format!("Couldn't create {}", output.display()))?;
+    let mut writer = CountingWriter {
+        writer: BufWriter::new(file),
+        written: 0,
+    };
+    let write_context = || format!("Couldn't write {}", output.display());
+    writer
+        .write_all(bytemuck::bytes_of(&Header {
+            magic: MAGIC,
+            version: BUNDLE_VERSION,
+            entry_count: sources.len() as u32,
+        }))
+        .with_context(write_context)?;
+    writer
+        .write_all(bytemuck::cast_slice(&toc))
+        .with_context(write_context)?;
+    for (name, _) in &sources {
+        writer
+            .write_all(name.as_bytes())
+            .with_context(write_context)?;
+    }
+    for ((name, source), toc_entry) in sources.iter().zip(&toc) {
+        let padding = toc_entry.offset as usize - writer.written;
+        writer
+            .write_all(&vec![0; padding])
+            .with_context(write_context)?;
+        let data = match source {
+            Source::File(path) => Cow::Owned(
+                fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))?,
+            ),
+            Source::Generated(data) => Cow::Borrowed(*data),
+        };
+        ensure!(
+            data.len() as u64 == toc_entry.size,
+            "{} changed while it was being packed",
+            name
+        );
+        writer.write_all(&data).with_context(write_context)?;
+    }
+    writer.writer.flush().with_context(write_context)?;
+    Ok(sources.len())
+}
+
+/// Keeps count of the bytes written, which the padding before each entry's data depends on
+struct CountingWriter<W> {
+    writer: W,
+    written: usize,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.written += bytes.len();
+        Ok(())
+    }
+}
+
+fn align(offset: usize) -> usize {
+    offset.div_ceil(DATA_ALIGNMENT) * DATA_ALIGNMENT
+}
+
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(directory)
+        .with_context(|| format!("Couldn't read {}", directory.display()))?;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("Couldn't read {}", directory.display()))?
+            .path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Where files are read from, loose on disk or out of a bundle in shipping builds
+pub enum Assets {
+    Loose,
+    Bundle(Bundle),
+}
+
+impl Assets {
+    ///Fn: Read: The file at path, relative to the working directory when loose
+    ///Params: Files in a bundle are borrowed straight from the mapping rather than copied
+    pub fn read(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        match self {
+            Assets::Loose => fs::read(path)
+                .map(Cow::Owned)
+                .with_context(|| format!("Couldn't read {}", path)),
+            Assets::Bundle(bundle) => bundle
+                .get(path)
+                .map(Cow::Borrowed)
+                .with_context(|| format!("{} isn't in {}", path, bundle.path().display())),
+        }
+    }
+
+    pub fn read_to_string(&self, path: &str) -> Result<String> {
+        let bytes = self.read(path)?;
+        let source =
+            std::str::from_utf8(&bytes).with_context(|| format!("{} isn't valid UTF-8", path))?;
+        Ok(source.to_string())
+    }
+
+    ///Fn: Shader: The WGSL source of a file in the shader directory, loose or from the bundle
+    ///Params: built_in is the copy compiled into the binary, used when the file can't be read
+    pub fn shader(&self, name: &str, built_in: &'static str) -> Cow<'static, str> {
+        let path = format!("{}/{}", SHADER_PATH, name);
+        match self.read_to_string(&path) {
+            Ok(source) => Cow::Owned(source),
+            Err(e) => {
+                // Loose builds run from elsewhere don't have the directory, a bundle should
+                if let Assets::Bundle(_) = self {
+                    log::warn!("{:?}, using the built in copy", e);
+                }
+                Cow::Borrowed(built_in)
+            }
+        }
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        match self {
+            Assets::Loose => Path::new(path).exists(),
+            Assets::Bundle(bundle) => bundle.get(path).is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Fn: Scratch: An empty directory of its own for each test, under the system temp directory
+    fn scratch(test: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("ashen-bundle-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    ///Fn: Built: A bundle holding one small file, to be broken by the tests
+    fn built(directory: &Path) -> PathBuf {
+        let output = directory.join("test.bundle");
+        let generated = vec![("assets/test.png".to_string(), b"not really a png".to_vec())];
+        build(&[] as &[&str], &generated, &output).unwrap();
+        output
+    }
+
+    ///Fn: Open Error: The error opening path gives, failing the test if it opens
+    fn open_error(path: &Path) -> String {
+        match Bundle::open(path) {
+            Ok(_) => panic!("{} opened", path.display()),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn patch(path: &Path, offset: usize, bytes: &[u8]) {
+        let mut data = fs::read(path).unwrap();
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn bundles_read_back_what_was_packed() {
+        let directory = scratch("round-trip");
+        let assets = directory.join("assets");
+        fs::create_dir_all(assets.join("shader")).unwrap();
+        fs::write(assets.join("a.png"), [1, 2, 3]).unwrap();
+        fs::write(assets.join("shader").join("b.wgsl"), "fn main() {}").unwrap();
+        let png_name = assets.join("a.png").to_str().unwrap().to_string();
+        let wgsl_name = assets.join("shader").join("b.wgsl");
+        let wgsl_name = wgsl_name.to_str().unwrap();
+        let generated = vec![
+            ("meshes/c.mesh".to_string(), vec![7; 40]),
+            // A file packed under the same name wins
+            (png_name.clone(), vec![9; 3]),
+        ];
+        let output = directory.join("test.bundle");
+        assert_eq!(build(&[&assets], &generated, &output).unwrap(), 3);
+        assert!(!directory.join("test.bundle.partial").exists());
+
+        let bundle = Bundle::open(&output).unwrap();
+        let mut kinds = bundle
+            .entries()
+            .map(|(name, entry)| (name.to_string(), entry.kind))
+            .collect::<Vec<_>>();
+        kinds.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut expected = vec![
+            (png_name.clone(), EntryKind::Texture),
+            (wgsl_name.to_string(), EntryKind::Shader),
+            ("meshes/c.mesh".to_string(), EntryKind::Mesh),
+        ];
+        expected.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(kinds, expected);
+        assert_eq!(bundle.get(&png_name), Some(&[1, 2, 3][..]));
+        assert_eq!(bundle.get(wgsl_name), Some(&b"fn main() {}"[..]));
+        assert_eq!(bundle.get("meshes/c.mesh"), Some(&[7; 40][..]));
+        assert_eq!(bundle.get("meshes/missing.mesh"), None);
+        for (_, entry) in bundle.entries() {
+            assert_eq!(entry.offset % DATA_ALIGNMENT, 0);
+        }
+
+        let assets = Assets::Bundle(bundle);
+        assert_eq!(assets.read_to_string(wgsl_name).unwrap(), "fn main() {}");
+        assert!(assets.read("meshes/missing.mesh").is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn failed_builds_leave_nothing_behind() {
+        let directory = scratch("failed-build");
+        let output = directory.join("test.bundle");
+        assert!(build(&[directory.join("missing")], &[], &output).is_err());
+        assert!(!output.exists());
+        assert!(!directory.join("test.bundle.partial").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn wrong_magic_or_version_is_an_error() {
+        let directory = scratch("magic-version");
+        let output = built(&directory);
+        patch(&output, 0, b"NOTAPAK!");
+        assert!(open_error(&output).contains("isn't a bundle"));
+
+        let output = built(&directory);
+        patch(&output, 8, &(BUNDLE_VERSION + 1).to_ne_bytes());
+        assert!(open_error(&output).contains("version"));
+
+        fs::write(&output, &MAGIC[..4]).unwrap();
+        assert!(open_error(&output).contains("too short"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn cut_short_table_of_contents_is_an_error() {
+        let directory = scratch("short-toc");
+        let output = built(&directory);
+        let data = fs::read(&output).unwrap();
+        fs::write(&output, &data[..mem::size_of::<Header>() + 8]).unwrap();
+        assert!(open_error(&output).contains("cut short"));
+
+        // Claiming more entries than the file holds
+        let output = built(&directory);
+        patch(&output, 12, &u32::MAX.to_ne_bytes());
+        assert!(open_error(&output).contains("cut short"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn entries_past_the_end_are_an_error() {
+        let directory = scratch("past-end");
+        let toc = mem::size_of::<Header>();
+        let offset_field = toc + 8;
+        let size_field = toc + 16;
+
+        let output = built(&directory);
+        let length = fs::metadata(&output).unwrap().len();
+        patch(&output, size_field, &length.to_ne_bytes());
+        assert!(open_error(&output).contains("runs past the end"));
+
+        let output = built(&directory);
+        patch(&output, offset_field, &length.to_ne_bytes());
+        assert!(open_error(&output).contains("runs past the end"));
+
+        // Big enough that offset plus size overflows
+        let output = built(&directory);
+        patch(&output, offset_field, &u64::MAX.to_ne_bytes());
+        assert!(open_error(&output).contains("runs past the end"));
+
+        let output = built(&directory);
+        patch(&output, toc, &length.to_ne_bytes());
+        assert!(open_error(&output).contains("broken name"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
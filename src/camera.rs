@@ -1,15 +1,54 @@
 use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
 
-#[rustfmt::skip]
-#[allow(dead_code)]
-pub const OPENGL_TO_WGPU_MATRIX: Mat4 = glam::const_mat4!(
-    [1.0, 0.0, 0.0, 0.0],
-    [0.0, 1.0, 0.0, 0.0],
-    [0.0, 0.0, 0.5, 0.0],
-    [0.0, 0.0, 0.5, 1.0]
-);
+/// Which end of the 0..1 depth range is nearest the camera
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DepthMode {
+    /// Near maps to 0 and far to 1
+    Standard,
+    /// Near maps to 1 and far to 0, spreading float precision evenly over distance
+    ReverseZ,
+}
+
+impl DepthMode {
+    ///Fn: Compare: The depth test to use in place of one written for standard depth
+    pub fn compare(self, compare: wgpu::CompareFunction) -> wgpu::CompareFunction {
+        use wgpu::CompareFunction::*;
+        match (self, compare) {
+            (DepthMode::Standard, compare) => compare,
+            (DepthMode::ReverseZ, Less) => Greater,
+            (DepthMode::ReverseZ, LessEqual) => GreaterEqual,
+            (DepthMode::ReverseZ, Greater) => Less,
+            (DepthMode::ReverseZ, GreaterEqual) => LessEqual,
+            (DepthMode::ReverseZ, compare) => compare,
+        }
+    }
+
+    ///Fn: Far Depth: The depth of the far plane, which depth buffers are cleared to
+    pub fn far_depth(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::ReverseZ => 0.0,
+        }
+    }
+}
 
+/// How the camera maps view space onto the screen
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Perspective with the camera's fovy, znear and zfar
+    Perspective,
+    /// Parallel projection showing `height` world units vertically, for 2D, isometric and CAD views
+    Orthographic { height: f32 },
+    /// Perspective without a far plane, near maps to depth 1 and infinity to 0.
+    /// Only usable with DepthMode::ReverseZ, zfar still bounds light culling.
+    InfinitePerspectiveReverseZ,
+}
+
+#[derive(Clone, Debug)]
 pub struct Camera {
+    pub projection: Projection,
+    pub depth_mode: DepthMode,
     pub eye: Vec3,
     pub target: Vec3,
     pub up: Vec3,
@@ -23,15 +62,120 @@ impl Camera {
     pub fn build_view_projection_matrix(&self) -> glam::Mat4 {
         // 1.
         let view = glam::Mat4::look_at_rh(self.eye, self.target, self.up);
-        // 2.
-        let proj = glam::Mat4::perspective_rh(
-            (self.fovy).to_radians(),
-            self.aspect,
-            self.znear,
-            self.zfar,
-        );
 
         // 3.
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
+        self.build_projection_matrix() * view
+    }
+
+    ///Fn: Build Projection Matrix: The projection alone, mapping depth to wgpu's 0..1 range
+    ///Params: Reverse-Z swaps the near and far planes, so near lands on 1
+    pub fn build_projection_matrix(&self) -> glam::Mat4 {
+        // 2. glam's right-handed projections already map depth to 0..1, as wgpu expects
+        let (near, far) = match self.depth_mode {
+            DepthMode::Standard => (self.znear, self.zfar),
+            DepthMode::ReverseZ => (self.zfar, self.znear),
+        };
+        match self.projection {
+            Projection::Perspective => {
+                glam::Mat4::perspective_rh((self.fovy).to_radians(), self.aspect, near, far)
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height * 0.5;
+                let half_width = half_height * self.aspect;
+                glam::Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    near,
+                    far,
+                )
+            }
+            // Already maps straight to the reversed 1..0 range
+            Projection::InfinitePerspectiveReverseZ => glam::Mat4::perspective_infinite_reverse_rh(
+                (self.fovy).to_radians(),
+                self.aspect,
+                self.znear,
+            ),
+        }
+    }
+
+    ///Fn: View Extent: Half the width and height of the view volume, at unit depth for perspective
+    ///Params: Returns the half extents and whether they're constant with depth (orthographic)
+    pub fn view_extent(&self) -> (glam::Vec2, bool) {
+        match self.projection {
+            Projection::Orthographic { height } => {
+                let half_height = height * 0.5;
+                (glam::vec2(half_height * self.aspect, half_height), true)
+            }
+            Projection::Perspective | Projection::InfinitePerspectiveReverseZ => {
+                let tan_half_fovy = (self.fovy.to_radians() * 0.5).tan();
+                (
+                    glam::vec2(tan_half_fovy * self.aspect, tan_half_fovy),
+                    false,
+                )
+            }
+        }
+    }
+
+    ///Fn: Place: Moves the eye to a world matrix's translation, looking down its -Z axis
+    pub fn place(&mut self, world: Mat4) {
+        self.eye = world.w_axis.truncate();
+        self.target = self.eye - world.z_axis.truncate().normalize();
+    }
+}
+
+/// Per-frame camera data for shaders, mirrored by the struct in shader/camera.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    pub view: Mat4,
+    pub proj: Mat4,
+    pub view_proj: Mat4,
+    pub inv_view: Mat4,
+    pub inv_proj: Mat4,
+    pub eye_position: [f32; 4],
+    pub viewport_size: [f32; 2],
+    pub znear: f32,
+    pub zfar: f32,
+    /// Seconds since startup
+    pub time: f32,
+    // WGSL rounds uniform structs up to 16 bytes
+    _padding: [f32; 3],
+}
+
+// Catches the Rust layout drifting from shader/camera.wgsl
+const _: () = assert!(std::mem::size_of::<CameraUniform>() == 368);
+
+impl CameraUniform {
+    pub fn new() -> Self {
+        Self {
+            view: Mat4::IDENTITY,
+            proj: Mat4::IDENTITY,
+            view_proj: Mat4::IDENTITY,
+            inv_view: Mat4::IDENTITY,
+            inv_proj: Mat4::IDENTITY,
+            eye_position: [0.0; 4],
+            viewport_size: [1.0; 2],
+            znear: 0.1,
+            zfar: 100.0,
+            time: 0.0,
+            _padding: [0.0; 3],
+        }
+    }
+
+    ///Fn: Update: Refreshes everything from the camera for this frame
+    ///Params: viewport_size is in pixels, time in seconds
+    pub fn update(&mut self, camera: &Camera, viewport_size: [f32; 2], time: f32) {
+        self.view = Mat4::look_at_rh(camera.eye, camera.target, camera.up);
+        self.proj = camera.build_projection_matrix();
+        self.view_proj = self.proj * self.view;
+        self.inv_view = self.view.inverse();
+        self.inv_proj = self.proj.inverse();
+        self.eye_position = camera.eye.extend(1.0).into();
+        self.viewport_size = viewport_size;
+        self.znear = camera.znear;
+        self.zfar = camera.zfar;
+        self.time = time;
     }
 }
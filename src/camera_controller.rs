@@ -0,0 +1,278 @@
+use glam::Vec3;
+
+use crate::{camera::Camera, input::InputMap};
+
+/// Keeps the pitch just short of straight up or down, where look_at flips
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+const MIN_DISTANCE: f32 = 1.0;
+const MAX_DISTANCE: f32 = 50.0;
+
+/// How the keyboard and mouse move the camera
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Circles around camera.target
+    Orbit,
+    /// First person free flight with mouse look
+    Fly,
+}
+
+/// An orbit pose, with the eye placed on a sphere around the target
+#[derive(Copy, Clone, Debug)]
+struct Orbit {
+    target: Vec3,
+    distance: f32,
+    /// Direction from the target to the eye, in radians
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Orbit {
+    fn from_camera(camera: &Camera) -> Self {
+        let offset = camera.eye - camera.target;
+        let distance = offset.length().clamp(MIN_DISTANCE, MAX_DISTANCE);
+        let direction = offset.normalize();
+        Self {
+            target: camera.target,
+            distance,
+            yaw: direction.z.atan2(direction.x),
+            pitch: direction.y.asin().clamp(-MAX_PITCH, MAX_PITCH),
+        }
+    }
+
+    fn eye(&self) -> Vec3 {
+        self.target
+            + Vec3::new(
+                self.pitch.cos() * self.yaw.cos(),
+                self.pitch.sin(),
+                self.pitch.cos() * self.yaw.sin(),
+            ) * self.distance
+    }
+
+    ///Fn: Approach: Moves this pose towards goal by the given fraction
+    fn approach(&mut self, goal: &Orbit, amount: f32) {
+        self.target = self.target.lerp(goal.target, amount);
+        self.distance += (goal.distance - self.distance) * amount;
+        self.yaw += (goal.yaw - self.yaw) * amount;
+        self.pitch += (goal.pitch - self.pitch) * amount;
+    }
+}
+
+pub struct CameraController {
+    pub mode: CameraMode,
+    /// Where the orbit camera is drawn this frame, easing towards orbit_goal
+    orbit: Orbit,
+    orbit_goal: Orbit,
+    /// How quickly the orbit eases towards its goal, per second
+    damping: f32,
+    /// Units per second
+    speed: f32,
+    /// Radians per second when rotating with the keys
+    rotation_speed: f32,
+    sprint_multiplier: f32,
+    /// Radians of rotation per pixel of mouse motion
+    sensitivity: f32,
+    yaw: f32,
+    pitch: f32,
+    mouse_delta: (f32, f32),
+    scroll_delta: f32,
+    /// Analog movement from -1 to 1, x right, y up and z forward
+    move_axis: Vec3,
+    /// Analog turning from -1 to 1, scaled by rotation_speed like the keys
+    turn_axis: (f32, f32),
+    is_rotating: bool,
+    is_panning: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+    is_sprint_pressed: bool,
+}
+
+impl CameraController {
+    pub fn new(speed: f32, camera: &Camera) -> Self {
+        let orbit = Orbit::from_camera(camera);
+        Self {
+            mode: CameraMode::Orbit,
+            orbit,
+            orbit_goal: orbit,
+            damping: 12.0,
+            speed,
+            rotation_speed: 2.0,
+            sprint_multiplier: 3.0,
+            sensitivity: 0.003,
+            yaw: 0.0,
+            pitch: 0.0,
+            mouse_delta: (0.0, 0.0),
+            scroll_delta: 0.0,
+            move_axis: Vec3::ZERO,
+            turn_axis: (0.0, 0.0),
+            is_rotating: false,
+            is_panning: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+            is_sprint_pressed: false,
+        }
+    }
+
+    ///Fn: Set Mode: Switches between orbit and fly, picking up the camera's current view direction
+    pub fn set_mode(&mut self, mode: CameraMode, camera: &Camera) {
+        let direction = (camera.target - camera.eye).normalize();
+        match mode {
+            CameraMode::Orbit => {
+                // Orbit around whatever is in front of the camera at the last orbit distance
+                let target = camera.eye + direction * self.orbit_goal.distance;
+                self.orbit = Orbit::from_camera(&Camera { target, ..*camera });
+                self.orbit_goal = self.orbit;
+            }
+            CameraMode::Fly => {
+                self.yaw = direction.z.atan2(direction.x);
+                self.pitch = direction.y.asin().clamp(-MAX_PITCH, MAX_PITCH);
+            }
+        }
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll_delta = 0.0;
+        self.mode = mode;
+    }
+
+    ///Fn: Process Input: Reads this frame's actions and axes, once per frame before update_camera
+    pub fn process_input(&mut self, input: &InputMap) {
+        self.is_up_pressed = input.is_pressed("move_up");
+        self.is_down_pressed = input.is_pressed("move_down");
+        self.is_sprint_pressed = input.is_pressed("sprint");
+        self.is_forward_pressed = input.is_pressed("move_forward");
+        self.is_backward_pressed = input.is_pressed("move_backward");
+        self.is_left_pressed = input.is_pressed("move_left");
+        self.is_right_pressed = input.is_pressed("move_right");
+        self.is_rotating = input.is_pressed("rotate");
+        self.is_panning = input.is_pressed("pan");
+
+        // Mouse look while flying, dragging only while a drag button is held in orbit
+        if self.mode == CameraMode::Fly || self.is_rotating || self.is_panning {
+            self.mouse_delta.0 += input.axis_value("look_x");
+            self.mouse_delta.1 += input.axis_value("look_y");
+        }
+        self.scroll_delta += input.axis_value("zoom");
+        self.move_axis = Vec3::new(
+            input.axis_value("move_x"),
+            input.axis_value("move_y"),
+            input.axis_value("move_z"),
+        );
+        self.turn_axis = (input.axis_value("turn_x"), input.axis_value("turn_y"));
+    }
+
+    ///Fn: Update Camera: Updates the transform of the Camera
+    ///Params: delta is the time step in seconds, key movement is scaled by it
+    pub fn update_camera(&mut self, camera: &mut Camera, delta: f32) {
+        match self.mode {
+            CameraMode::Orbit => self.update_orbit(camera, delta),
+            CameraMode::Fly => self.update_fly(camera, delta),
+        }
+    }
+
+    fn update_orbit(&mut self, camera: &mut Camera, delta: f32) {
+        let (dx, dy) = self.mouse_delta;
+        self.mouse_delta = (0.0, 0.0);
+        let goal = &mut self.orbit_goal;
+
+        if self.is_rotating {
+            goal.yaw += dx * self.sensitivity;
+            goal.pitch += dy * self.sensitivity;
+        }
+        if self.is_panning {
+            // Pan in the view plane, scaled so the target keeps up with the cursor at any distance
+            let forward = (goal.target - goal.eye()).normalize();
+            let right = forward.cross(camera.up).normalize();
+            let up = right.cross(forward);
+            let pan_scale = goal.distance * self.sensitivity * 0.5;
+            goal.target += (up * dy - right * dx) * pan_scale;
+        }
+
+        let key_rotation = self.rotation_speed * delta;
+        if self.is_right_pressed {
+            goal.yaw -= key_rotation;
+        }
+        if self.is_left_pressed {
+            goal.yaw += key_rotation;
+        }
+        if self.is_up_pressed {
+            goal.pitch += key_rotation;
+        }
+        if self.is_down_pressed {
+            goal.pitch -= key_rotation;
+        }
+        if self.is_forward_pressed {
+            goal.distance -= self.speed * delta;
+        }
+        if self.is_backward_pressed {
+            goal.distance += self.speed * delta;
+        }
+        // Sticks and triggers do what the keys do, with the right stick turning like a drag
+        goal.yaw -= (self.move_axis.x + self.turn_axis.0) * key_rotation;
+        goal.pitch += (self.move_axis.y + self.turn_axis.1) * key_rotation;
+        goal.distance -= self.move_axis.z * self.speed * delta;
+        // Each scroll line zooms by a tenth of the distance, so it feels the same near and far
+        goal.distance *= 1.0 - self.scroll_delta * 0.1;
+        self.scroll_delta = 0.0;
+
+        goal.pitch = goal.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+        goal.distance = goal.distance.clamp(MIN_DISTANCE, MAX_DISTANCE);
+
+        // Exponential decay, so the easing is the same at any frame rate
+        self.orbit
+            .approach(&self.orbit_goal, 1.0 - (-self.damping * delta).exp());
+        camera.target = self.orbit.target;
+        camera.eye = self.orbit.eye();
+    }
+
+    fn update_fly(&mut self, camera: &mut Camera, delta: f32) {
+        let (dx, dy) = self.mouse_delta;
+        self.mouse_delta = (0.0, 0.0);
+        let key_rotation = self.rotation_speed * delta;
+        self.yaw += dx * self.sensitivity + self.turn_axis.0 * key_rotation;
+        self.pitch = (self.pitch - dy * self.sensitivity + self.turn_axis.1 * key_rotation)
+            .clamp(-MAX_PITCH, MAX_PITCH);
+
+        let forward = Vec3::new(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.sin(),
+        );
+        let right = forward.cross(camera.up).normalize();
+
+        let mut speed = self.speed;
+        if self.is_sprint_pressed {
+            speed *= self.sprint_multiplier;
+        }
+        let mut movement =
+            right * self.move_axis.x + camera.up * self.move_axis.y + forward * self.move_axis.z;
+        if self.is_forward_pressed {
+            movement += forward;
+        }
+        if self.is_backward_pressed {
+            movement -= forward;
+        }
+        if self.is_right_pressed {
+            movement += right;
+        }
+        if self.is_left_pressed {
+            movement -= right;
+        }
+        if self.is_up_pressed {
+            movement += camera.up;
+        }
+        if self.is_down_pressed {
+            movement -= camera.up;
+        }
+
+        // Keys move at full speed in any direction, a partly pushed stick moves slower
+        camera.eye += movement.clamp_length_max(1.0) * speed * delta;
+        camera.target = camera.eye + forward;
+    }
+}
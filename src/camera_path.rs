@@ -0,0 +1,277 @@
+use std::ops::{Add, Mul, Sub};
+
+use glam::Vec3;
+
+use crate::camera::Camera;
+
+/// Seconds between a recorded keyframe and the one before it
+const RECORD_SPACING: f32 = 2.0;
+
+/// A camera pose at a point on the path
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    /// Seconds from the start of the path
+    pub time: f32,
+    pub eye: Vec3,
+    pub target: Vec3,
+    /// Degrees, as in Camera
+    pub fovy: f32,
+}
+
+impl Keyframe {
+    pub fn from_camera(camera: &Camera, time: f32) -> Self {
+        Self {
+            time,
+            eye: camera.eye,
+            target: camera.target,
+            fovy: camera.fovy,
+        }
+    }
+
+    ///Fn: Apply: Moves the camera to this pose, leaving its projection and up vector alone
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.eye;
+        camera.target = self.target;
+        camera.fovy = self.fovy;
+    }
+}
+
+/// Keyframes sorted by time, interpolated with a Catmull-Rom spline so the camera
+/// passes through every keyframe without stopping at it.
+#[derive(Clone, Debug, Default)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keyframes }
+    }
+
+    ///Fn: Add Keyframe: Inserts a keyframe in time order, after any at the same time
+    pub fn add_keyframe(&mut self, keyframe: Keyframe) {
+        let index = self
+            .keyframes
+            .partition_point(|existing| existing.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    ///Fn: Record: Appends the camera's current pose a fixed spacing after the last keyframe
+    pub fn record(&mut self, camera: &Camera) -> Keyframe {
+        let time = self
+            .keyframes
+            .last()
+            .map_or(0.0, |last| last.time + RECORD_SPACING);
+        let keyframe = Keyframe::from_camera(camera, time);
+        self.add_keyframe(keyframe);
+        keyframe
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    ///Fn: Duration: Time of the last keyframe, zero for an empty path
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |last| last.time)
+    }
+
+    ///Fn: Sample: The interpolated pose at time, clamped to the ends of the path
+    ///Params: Returns None for an empty path
+    pub fn sample(&self, time: f32) -> Option<Keyframe> {
+        let keyframes = &self.keyframes;
+        let first = keyframes.first()?;
+        let last = keyframes.last()?;
+        if time <= first.time {
+            return Some(Keyframe { time, ..*first });
+        }
+        if time >= last.time {
+            return Some(Keyframe { time, ..*last });
+        }
+
+        // The segment from keyframes[index] to keyframes[index + 1] contains time
+        let index = keyframes.partition_point(|keyframe| keyframe.time <= time) - 1;
+        let k0 = &keyframes[index.saturating_sub(1)];
+        let k1 = &keyframes[index];
+        let k2 = &keyframes[index + 1];
+        let k3 = &keyframes[(index + 2).min(keyframes.len() - 1)];
+        let times = [k0.time, k1.time, k2.time, k3.time];
+        Some(Keyframe {
+            time,
+            eye: catmull_rom([k0.eye, k1.eye, k2.eye, k3.eye], times, time),
+            target: catmull_rom([k0.target, k1.target, k2.target, k3.target], times, time),
+            fovy: catmull_rom([k0.fovy, k1.fovy, k2.fovy, k3.fovy], times, time),
+        })
+    }
+}
+
+///Fn: Catmull Rom: Evaluates the spline between points[1] and points[2] at time
+///Params: Tangents are scaled by the keyframe times, so unevenly spaced keyframes keep a steady speed
+fn catmull_rom<T>(points: [T; 4], times: [f32; 4], time: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let [p0, p1, p2, p3] = points;
+    let [t0, t1, t2, t3] = times;
+    let span = t2 - t1;
+    // At the ends of the path the missing neighbour is the end keyframe itself, so the tangent
+    // there is the chord between it and its one neighbour. Keyframes sharing a time get a flat tangent.
+    let tangent = |before: T, after: T, dt: f32| {
+        if dt > 0.0 {
+            (after - before) * (span / dt)
+        } else {
+            (after - before) * 0.0
+        }
+    };
+    let m1 = tangent(p0, p2, t2 - t0);
+    let m2 = tangent(p1, p3, t3 - t1);
+
+    // Cubic Hermite basis
+    let s = (time - t1) / span;
+    let s2 = s * s;
+    let s3 = s2 * s;
+    p1 * (2.0 * s3 - 3.0 * s2 + 1.0)
+        + m1 * (s3 - 2.0 * s2 + s)
+        + p2 * (-2.0 * s3 + 3.0 * s2)
+        + m2 * (s3 - s2)
+}
+
+/// Plays a CameraPath back onto the Camera, taking over from the CameraController while playing
+pub struct CameraPathPlayer {
+    pub path: CameraPath,
+    pub playing: bool,
+    /// Starts over from the first keyframe when the end is reached
+    pub looping: bool,
+    time: f32,
+}
+
+impl CameraPathPlayer {
+    pub fn new(path: CameraPath) -> Self {
+        Self {
+            path,
+            playing: false,
+            looping: false,
+            time: 0.0,
+        }
+    }
+
+    ///Fn: Toggle Playback: Starts from the beginning, or stops where the camera is
+    ///Params: Returns whether it's now playing, a path needs two keyframes to play
+    pub fn toggle_playback(&mut self) -> bool {
+        self.playing = !self.playing && self.path.keyframes().len() > 1;
+        self.time = 0.0;
+        self.playing
+    }
+
+    ///Fn: Update Camera: Advances playback and moves the camera along the path
+    ///Params: delta is the time step in seconds, does nothing while stopped
+    pub fn update_camera(&mut self, camera: &mut Camera, delta: f32) {
+        if !self.playing {
+            return;
+        }
+        self.time += delta;
+        let duration = self.path.duration();
+        if self.time > duration {
+            if self.looping && duration > 0.0 {
+                self.time %= duration;
+            } else {
+                self.time = duration;
+                self.playing = false;
+            }
+        }
+        if let Some(keyframe) = self.path.sample(self.time) {
+            keyframe.apply(camera);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, x: f32, fovy: f32) -> Keyframe {
+        Keyframe {
+            time,
+            eye: Vec3::new(x, 1.0, 5.0),
+            target: Vec3::new(x, 0.0, 0.0),
+            fovy,
+        }
+    }
+
+    fn assert_pose(sampled: Keyframe, expected: Keyframe) {
+        assert_eq!(sampled.time, expected.time);
+        assert!(
+            sampled.eye.abs_diff_eq(expected.eye, 1e-5)
+                && sampled.target.abs_diff_eq(expected.target, 1e-5)
+                && (sampled.fovy - expected.fovy).abs() < 1e-4,
+            "sampled {:?}, expected {:?}",
+            sampled,
+            expected
+        );
+    }
+
+    #[test]
+    fn keyframes_are_hit_at_their_times() {
+        let keyframes = vec![
+            keyframe(0.0, 0.0, 45.0),
+            keyframe(1.0, 3.0, 60.0),
+            keyframe(3.0, -2.0, 30.0),
+            keyframe(4.0, 1.0, 45.0),
+        ];
+        // Out of order on the way in
+        let path = CameraPath::new(keyframes.iter().rev().copied().collect());
+        assert_eq!(path.keyframes(), &keyframes[..]);
+        assert_eq!(path.duration(), 4.0);
+        for &keyframe in &keyframes {
+            assert_pose(path.sample(keyframe.time).unwrap(), keyframe);
+        }
+        // Between keyframes the spline stays between them, and isn't stuck at either
+        let between = path.sample(0.5).unwrap();
+        assert!(between.eye.x > 0.0 && between.eye.x < 3.0);
+    }
+
+    #[test]
+    fn evenly_spaced_keyframes_on_a_line_are_followed_steadily() {
+        let path = CameraPath::new(
+            (0..4)
+                .map(|i| keyframe(i as f32, i as f32 * 2.0, 45.0))
+                .collect(),
+        );
+        for &time in &[0.25, 0.5, 1.5, 2.75] {
+            assert_pose(path.sample(time).unwrap(), keyframe(time, time * 2.0, 45.0));
+        }
+    }
+
+    #[test]
+    fn samples_clamp_to_the_ends() {
+        let path = CameraPath::new(vec![keyframe(1.0, 0.0, 45.0), keyframe(2.0, 4.0, 60.0)]);
+        assert_pose(path.sample(-3.0).unwrap(), keyframe(-3.0, 0.0, 45.0));
+        assert_pose(path.sample(1.0).unwrap(), keyframe(1.0, 0.0, 45.0));
+        assert_pose(path.sample(2.0).unwrap(), keyframe(2.0, 4.0, 60.0));
+        assert_pose(path.sample(10.0).unwrap(), keyframe(10.0, 4.0, 60.0));
+    }
+
+    #[test]
+    fn short_paths_still_sample() {
+        let mut path = CameraPath::default();
+        assert_eq!(path.sample(0.0), None);
+        assert_eq!(path.duration(), 0.0);
+
+        path.add_keyframe(keyframe(2.0, 1.0, 50.0));
+        for &time in &[0.0, 2.0, 5.0] {
+            assert_pose(path.sample(time).unwrap(), keyframe(time, 1.0, 50.0));
+        }
+
+        // A second keyframe at the same time goes after the first, and the jump doesn't divide by zero
+        path.add_keyframe(keyframe(2.0, 3.0, 50.0));
+        path.add_keyframe(keyframe(4.0, 5.0, 50.0));
+        assert_eq!(path.keyframes()[1].eye.x, 3.0);
+        let sampled = path.sample(3.0).unwrap();
+        assert!(sampled.eye.is_finite() && sampled.eye.x > 3.0 && sampled.eye.x < 5.0);
+    }
+}
@@ -0,0 +1,235 @@
+use std::borrow::Cow;
+
+use glam::Mat4;
+use wgpu::util::DeviceExt;
+
+use crate::{bundle::Assets, camera::Camera};
+
+const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+/// Generated into the WGSL by ClusteredLighting::wgsl, so the shaders can't disagree with it
+const MAX_LIGHTS_PER_CLUSTER: u32 = 100;
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterUniforms {
+    view: Mat4,
+    eye_position: [f32; 4],
+    /// Cluster counts along x, y and z, then the number of lights
+    grid: [u32; 4],
+    screen_size: [f32; 2],
+    znear: f32,
+    zfar: f32,
+    /// Half extents of the view volume, at unit depth unless orthographic
+    view_extent: [f32; 2],
+    /// Non-zero when view_extent doesn't grow with depth
+    orthographic: u32,
+    _padding: u32,
+}
+
+// Catches the Rust layout drifting from shader/cluster_common.wgsl
+const _: () = assert!(std::mem::size_of::<ClusterUniforms>() == 128);
+
+impl ClusterUniforms {
+    fn new() -> Self {
+        Self {
+            view: Mat4::IDENTITY,
+            eye_position: [0.0; 4],
+            grid: [CLUSTER_GRID[0], CLUSTER_GRID[1], CLUSTER_GRID[2], 0],
+            screen_size: [1.0; 2],
+            znear: 0.1,
+            zfar: 100.0,
+            view_extent: [1.0; 2],
+            orthographic: 0,
+            _padding: 0,
+        }
+    }
+
+    fn update(&mut self, camera: &Camera, viewport_size: [f32; 2], light_count: u32) {
+        self.view = Mat4::look_at_rh(camera.eye, camera.target, camera.up);
+        self.eye_position = camera.eye.extend(1.0).into();
+        self.grid[3] = light_count;
+        self.screen_size = viewport_size;
+        self.znear = camera.znear;
+        self.zfar = camera.zfar;
+        let (view_extent, orthographic) = camera.view_extent();
+        self.view_extent = view_extent.into();
+        self.orthographic = orthographic as u32;
+    }
+}
+
+/// Clustered forward lighting. A compute pass bins the point lights into a
+/// froxel grid built from the camera frustum every frame, and the forward
+/// shader reads the cluster's light list through bind_group at group 3.
+pub struct ClusteredLighting {
+    uniforms: ClusterUniforms,
+    uniform_buffer: wgpu::Buffer,
+    compute_pipeline: wgpu::ComputePipeline,
+    compute_bind_group: wgpu::BindGroup,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ClusteredLighting {
+    pub fn new(device: &wgpu::Device, assets: &Assets, light_buffer: &wgpu::Buffer) -> Self {
+        let uniforms = ClusterUniforms::new();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cluster Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let cluster_count = CLUSTER_GRID.iter().product::<u32>();
+        let cluster_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Light Lists"),
+            size: (cluster_count * (MAX_LIGHTS_PER_CLUSTER + 1)) as wgpu::BufferAddress
+                * std::mem::size_of::<u32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let entry = |binding, visibility, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    entry(
+                        0,
+                        wgpu::ShaderStage::COMPUTE,
+                        wgpu::BufferBindingType::Uniform,
+                    ),
+                    entry(
+                        1,
+                        wgpu::ShaderStage::COMPUTE,
+                        wgpu::BufferBindingType::Storage { read_only: true },
+                    ),
+                    entry(
+                        2,
+                        wgpu::ShaderStage::COMPUTE,
+                        wgpu::BufferBindingType::Storage { read_only: false },
+                    ),
+                ],
+                label: Some("cluster_compute_bind_group_layout"),
+            });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                entry(
+                    0,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BufferBindingType::Uniform,
+                ),
+                entry(
+                    1,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BufferBindingType::Storage { read_only: true },
+                ),
+                entry(
+                    2,
+                    wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BufferBindingType::Storage { read_only: true },
+                ),
+            ],
+            label: Some("cluster_bind_group_layout"),
+        });
+
+        let create_bind_group = |layout, label| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: light_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: cluster_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some(label),
+            })
+        };
+        let compute_bind_group =
+            create_bind_group(&compute_bind_group_layout, "cluster_compute_bind_group");
+        let bind_group = create_bind_group(&bind_group_layout, "cluster_bind_group");
+
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Cluster Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(format!(
+                "{}{}",
+                Self::wgsl(assets),
+                assets.shader("cluster.wgsl", include_str!("../shader/cluster.wgsl"))
+            ))),
+            flags: wgpu::ShaderFlags::default(),
+        });
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Cluster Pipeline Layout"),
+                bind_group_layouts: &[&compute_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cluster Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &module,
+            entry_point: "main_cs",
+        });
+
+        Self {
+            uniforms,
+            uniform_buffer,
+            compute_pipeline,
+            compute_bind_group,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    ///Fn: WGSL: MAX_LIGHTS_PER_CLUSTER and the cluster structs, to put in front of the shaders that use clusters
+    pub fn wgsl(assets: &Assets) -> String {
+        format!(
+            "let MAX_LIGHTS_PER_CLUSTER: u32 = {}u;\n\n{}",
+            MAX_LIGHTS_PER_CLUSTER,
+            assets.shader(
+                "cluster_common.wgsl",
+                include_str!("../shader/cluster_common.wgsl")
+            )
+        )
+    }
+
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        viewport_size: [f32; 2],
+        light_count: u32,
+    ) {
+        self.uniforms.update(camera, viewport_size, light_count);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniforms]),
+        );
+    }
+
+    ///Fn: Cull Lights: Rebuilds every cluster's light list for this frame's camera
+    pub fn cull_lights(&self, encoder: &mut wgpu::CommandEncoder) {
+        let cluster_count = CLUSTER_GRID.iter().product::<u32>();
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light Culling Pass"),
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
+        compute_pass.dispatch(cluster_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
@@ -0,0 +1,480 @@
+use std::borrow::Cow;
+
+use glam::Mat4;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    bundle::Assets,
+    camera::{Camera, DepthMode},
+    instance::InstanceRaw,
+    light, pipeline, texture,
+};
+
+const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const MATERIAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+#[rustfmt::skip]
+const LIGHT_VOLUME_VERTICES: &[[f32; 3]] = &[
+    [-1.0, -1.0, -1.0], [-1.0, -1.0, 1.0], [-1.0, 1.0, -1.0], [-1.0, 1.0, 1.0],
+    [1.0, -1.0, -1.0], [1.0, -1.0, 1.0], [1.0, 1.0, -1.0], [1.0, 1.0, 1.0],
+];
+
+#[rustfmt::skip]
+const LIGHT_VOLUME_INDICES: &[u16] = &[
+    0, 3, 2, 0, 1, 3, // -x
+    4, 6, 7, 4, 7, 5, // +x
+    0, 4, 5, 0, 5, 1, // -y
+    2, 7, 6, 2, 3, 7, // +y
+    0, 6, 4, 0, 2, 6, // -z
+    1, 5, 7, 1, 7, 3, // +z
+];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DeferredUniforms {
+    view_proj: Mat4,
+    inv_view_proj: Mat4,
+    eye_position: [f32; 4],
+    /// Depth of empty background, 1 or 0 with reverse-Z
+    far_depth: f32,
+    _padding: [f32; 3],
+}
+
+impl DeferredUniforms {
+    fn new() -> Self {
+        Self {
+            view_proj: Mat4::IDENTITY,
+            inv_view_proj: Mat4::IDENTITY,
+            eye_position: [0.0; 4],
+            far_depth: 1.0,
+            _padding: [0.0; 3],
+        }
+    }
+
+    fn update(&mut self, camera: &Camera) {
+        self.view_proj = camera.build_view_projection_matrix();
+        self.inv_view_proj = self.view_proj.inverse();
+        self.eye_position = camera.eye.extend(1.0).into();
+        self.far_depth = camera.depth_mode.far_depth();
+    }
+}
+
+struct GBuffer {
+    albedo: texture::Texture,
+    normal: texture::Texture,
+    material: texture::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GBuffer {
+    fn new(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        layout: &wgpu::BindGroupLayout,
+        depth_texture: &texture::Texture,
+    ) -> Self {
+        let albedo = texture::Texture::create_render_target(
+            device,
+            sc_desc,
+            ALBEDO_FORMAT,
+            1,
+            "gbuffer_albedo",
+        );
+        let normal = texture::Texture::create_render_target(
+            device,
+            sc_desc,
+            NORMAL_FORMAT,
+            1,
+            "gbuffer_normal",
+        );
+        let material = texture::Texture::create_render_target(
+            device,
+            sc_desc,
+            MATERIAL_FORMAT,
+            1,
+            "gbuffer_material",
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&albedo.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&material.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+            ],
+            label: Some("gbuffer_bind_group"),
+        });
+
+        Self {
+            albedo,
+            normal,
+            material,
+            bind_group,
+        }
+    }
+}
+
+/// Deferred alternative to the forward pass. Geometry is drawn once into the
+/// G-buffer with the same material and camera bind groups as the forward
+/// pipeline, then lit by a full-screen pass and one additive volume per light.
+pub struct Deferred {
+    uniforms: DeferredUniforms,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    gbuffer_bind_group_layout: wgpu::BindGroupLayout,
+    gbuffer_pipeline: wgpu::RenderPipeline,
+    lighting_pipeline: wgpu::RenderPipeline,
+    light_volume_pipeline: wgpu::RenderPipeline,
+    light_volume_vertex_buffer: wgpu::Buffer,
+    light_volume_index_buffer: wgpu::Buffer,
+    gbuffer: GBuffer,
+    depth_mode: DepthMode,
+}
+
+impl Deferred {
+    ///Fn: New: Builds the deferred pipelines
+    ///Params: scene_layouts are the material, camera and SSAO layouts of the forward pipeline
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        assets: &Assets,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        scene_layouts: &[&wgpu::BindGroupLayout],
+        ssao_layout: &wgpu::BindGroupLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        depth_texture: &texture::Texture,
+        depth_mode: DepthMode,
+    ) -> Self {
+        let uniforms = DeferredUniforms::new();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Deferred Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("deferred_uniform_bind_group_layout"),
+            });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("deferred_uniform_bind_group"),
+        });
+
+        let gbuffer_texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type,
+            },
+            count: None,
+        };
+        let float = wgpu::TextureSampleType::Float { filterable: false };
+        let gbuffer_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    gbuffer_texture_entry(0, float),
+                    gbuffer_texture_entry(1, float),
+                    gbuffer_texture_entry(2, float),
+                    gbuffer_texture_entry(3, wgpu::TextureSampleType::Depth),
+                ],
+                label: Some("gbuffer_bind_group_layout"),
+            });
+
+        let shader = |label, source| {
+            device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(source),
+                flags: wgpu::ShaderFlags::default(),
+            })
+        };
+        let gbuffer_source = format!(
+            "{}{}{}",
+            assets.shader("camera.wgsl", include_str!("../shader/camera.wgsl")),
+            InstanceRaw::wgsl(assets),
+            assets.shader("gbuffer.wgsl", include_str!("../shader/gbuffer.wgsl"))
+        );
+        let gbuffer_module = shader("G-Buffer Shader", Cow::Owned(gbuffer_source));
+        let deferred_common = assets.shader(
+            "deferred_common.wgsl",
+            include_str!("../shader/deferred_common.wgsl"),
+        );
+        let lighting_source = format!(
+            "{}{}",
+            deferred_common,
+            assets.shader(
+                "deferred_lighting.wgsl",
+                include_str!("../shader/deferred_lighting.wgsl"),
+            )
+        );
+        let lighting_module = shader(
+            "Deferred Lighting Shader",
+            pipeline::fullscreen_wgsl(assets, &lighting_source),
+        );
+        let light_volume_source = format!(
+            "{}{}",
+            deferred_common,
+            assets.shader(
+                "deferred_light_volume.wgsl",
+                include_str!("../shader/deferred_light_volume.wgsl"),
+            )
+        );
+        let light_volume_module = shader(
+            "Deferred Light Volume Shader",
+            Cow::Owned(light_volume_source),
+        );
+
+        let gbuffer_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("G-Buffer Pipeline Layout"),
+                bind_group_layouts: scene_layouts,
+                push_constant_ranges: &[],
+            });
+        let gbuffer_pipeline = pipeline::create_render_pipeline(
+            device,
+            &gbuffer_pipeline_layout,
+            &[
+                pipeline::color_target(ALBEDO_FORMAT),
+                pipeline::color_target(NORMAL_FORMAT),
+                pipeline::color_target(MATERIAL_FORMAT),
+            ],
+            Some(pipeline::depth_stencil_state(
+                true,
+                depth_mode.compare(wgpu::CompareFunction::Less),
+            )),
+            vertex_layouts,
+            &gbuffer_module,
+            1,
+            "G-Buffer Pipeline",
+        );
+
+        let lighting_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Deferred Lighting Pipeline Layout"),
+                bind_group_layouts: &[
+                    &gbuffer_bind_group_layout,
+                    &uniform_bind_group_layout,
+                    ssao_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let lighting_pipeline = pipeline::create_fullscreen_pipeline(
+            device,
+            &lighting_pipeline_layout,
+            sc_desc.format,
+            &lighting_module,
+            "Deferred Lighting Pipeline",
+        );
+
+        let light_volume_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Deferred Light Volume Pipeline Layout"),
+                bind_group_layouts: &[&gbuffer_bind_group_layout, &uniform_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let light_volume_pipeline =
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Deferred Light Volume Pipeline"),
+                layout: Some(&light_volume_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &light_volume_module,
+                    entry_point: "main_vs",
+                    buffers: &[
+                        wgpu::VertexBufferLayout {
+                            array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                            step_mode: wgpu::InputStepMode::Vertex,
+                            attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                        },
+                        light::PointLight::desc(),
+                    ],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &light_volume_module,
+                    entry_point: "main_fs",
+                    targets: &[wgpu::ColorTargetState {
+                        format: sc_desc.format,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent::REPLACE,
+                        }),
+                        write_mask: wgpu::ColorWrite::ALL,
+                    }],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    // Back faces still cover the light when the camera is inside the volume
+                    cull_mode: Some(wgpu::Face::Front),
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    clamp_depth: false,
+                    conservative: false,
+                },
+                // The depth buffer is sampled by the pass, so the fragment shader does the
+                // rejection instead of the depth test
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+            });
+
+        let light_volume_vertex_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Light Volume Vertex Buffer"),
+                contents: bytemuck::cast_slice(LIGHT_VOLUME_VERTICES),
+                usage: wgpu::BufferUsage::VERTEX,
+            });
+        let light_volume_index_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Light Volume Index Buffer"),
+                contents: bytemuck::cast_slice(LIGHT_VOLUME_INDICES),
+                usage: wgpu::BufferUsage::INDEX,
+            });
+
+        let gbuffer = GBuffer::new(device, sc_desc, &gbuffer_bind_group_layout, depth_texture);
+
+        Self {
+            depth_mode,
+            uniforms,
+            uniform_buffer,
+            uniform_bind_group,
+            gbuffer_bind_group_layout,
+            gbuffer_pipeline,
+            lighting_pipeline,
+            light_volume_pipeline,
+            light_volume_vertex_buffer,
+            light_volume_index_buffer,
+            gbuffer,
+        }
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        depth_texture: &texture::Texture,
+    ) {
+        self.gbuffer = GBuffer::new(
+            device,
+            sc_desc,
+            &self.gbuffer_bind_group_layout,
+            depth_texture,
+        );
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        self.uniforms.update(camera);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniforms]),
+        );
+    }
+
+    ///Fn: Begin G-Buffer Pass: Clears the G-buffer and depth, ready for the scene to be drawn
+    ///Params: The caller binds the scene bind groups and buffers exactly as for the forward pass
+    pub fn begin_gbuffer_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        let clear = |view| wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store: true,
+            },
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("G-Buffer Pass"),
+            color_attachments: &[
+                clear(&self.gbuffer.albedo.view),
+                clear(&self.gbuffer.normal.view),
+                clear(&self.gbuffer.material.view),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.depth_mode.far_depth()),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.gbuffer_pipeline);
+        render_pass
+    }
+
+    ///Fn: Lighting Pass: Resolves the G-buffer into target_view
+    ///Params: lights is the light buffer from light::create_light_buffer holding light_count lights
+    pub fn lighting_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target_view: &wgpu::TextureView,
+        clear_color: wgpu::Color,
+        ssao_bind_group: &wgpu::BindGroup,
+        lights: &wgpu::Buffer,
+        light_count: u32,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Deferred Lighting Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.lighting_pipeline);
+        render_pass.set_bind_group(0, &self.gbuffer.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+        render_pass.set_bind_group(2, ssao_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        if light_count > 0 {
+            render_pass.set_pipeline(&self.light_volume_pipeline);
+            render_pass.set_vertex_buffer(0, self.light_volume_vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, lights.slice(..));
+            render_pass.set_index_buffer(
+                self.light_volume_index_buffer.slice(..),
+                wgpu::IndexFormat::Uint16,
+            );
+            render_pass.draw_indexed(0..LIGHT_VOLUME_INDICES.len() as u32, 0, 0..light_count);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::ops::Range;
+
+use crate::ecs::{MaterialHandle, MeshHandle};
+
+/// One draw call, a submesh drawn with a material for a range of a batch's instances
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrawItem {
+    pub material: MaterialHandle,
+    pub mesh: MeshHandle,
+    pub submesh: usize,
+    /// The batch whose instance buffer the draw reads
+    pub batch: usize,
+    pub instances: Range<u32>,
+}
+
+impl DrawItem {
+    /// Ordered by what's most expensive to change: material bind group, then mesh buffers
+    fn sort_key(&self) -> (MaterialHandle, MeshHandle, usize) {
+        (self.material, self.mesh, self.submesh)
+    }
+}
+
+/// A frame's draws. The opaque ones are sorted so draws sharing a material or mesh are next to
+/// each other and the pass only rebinds what changes, the blended ones stay back to front.
+#[derive(Default)]
+pub struct DrawList {
+    opaque: Vec<DrawItem>,
+    blended: Vec<DrawItem>,
+}
+
+impl DrawList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.opaque.clear();
+        self.blended.clear();
+    }
+
+    pub fn push_opaque(&mut self, item: DrawItem) {
+        self.opaque.push(item);
+    }
+
+    ///Fn: Push Blended: Adds a blended draw, which is drawn after every blended draw pushed before it
+    pub fn push_blended(&mut self, item: DrawItem) {
+        self.blended.push(item);
+    }
+
+    ///Fn: Sort: Groups the opaque draws by material and mesh, blending order is left alone
+    pub fn sort(&mut self) {
+        self.opaque.sort_by_key(DrawItem::sort_key);
+    }
+
+    ///Fn: Opaque: The draws for the opaque pipeline, cutouts included
+    pub fn opaque(&self) -> &[DrawItem] {
+        &self.opaque
+    }
+
+    ///Fn: Blended: The draws for the transparent pipeline, back to front across every batch
+    pub fn blended(&self) -> &[DrawItem] {
+        &self.blended
+    }
+}
+
+///Fn: Back To Front: Interleaves batches of blended instances into runs drawn farthest first
+///Params: distances are each batch's instance distances from the eye, in instance buffer order.
+///Params: Returns runs of (batch, instances), a batch is split wherever another's instance comes between
+///Params: or its instances aren't already in back to front order in the buffer.
+pub fn back_to_front(distances: &[(usize, Vec<f32>)]) -> Vec<(usize, Range<u32>)> {
+    // Each batch's instances farthest first. The sort is stable, so instances the buffer already
+    // holds in order stay next to each other and can be drawn as one run.
+    let orders = distances
+        .iter()
+        .map(|(_, batch)| {
+            let mut order = (0..batch.len() as u32).collect::<Vec<_>>();
+            order.sort_by(|&a, &b| {
+                batch[b as usize]
+                    .partial_cmp(&batch[a as usize])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            order
+        })
+        .collect::<Vec<_>>();
+    let mut next = vec![0; distances.len()];
+    let mut runs: Vec<(usize, Range<u32>)> = Vec::new();
+    let mut current = None;
+    loop {
+        let mut farthest: Option<(usize, f32)> = None;
+        for (index, (_, batch)) in distances.iter().enumerate() {
+            let distance = match orders[index].get(next[index]) {
+                Some(&instance) => batch[instance as usize],
+                None => continue,
+            };
+            // Ties go to the batch already being drawn, to keep runs long
+            let farther = match farthest {
+                None => true,
+                Some((_, best)) => distance > best || (distance == best && current == Some(index)),
+            };
+            if farther {
+                farthest = Some((index, distance));
+            }
+        }
+        let index = match farthest {
+            Some((index, _)) => index,
+            None => return runs,
+        };
+        let instance = orders[index][next[index]];
+        next[index] += 1;
+        match runs.last_mut() {
+            Some(run) if current == Some(index) && run.1.end == instance => {
+                run.1.end = instance + 1
+            }
+            _ => runs.push((distances[index].0, instance..instance + 1)),
+        }
+        current = Some(index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(material: usize, mesh: usize, batch: usize) -> DrawItem {
+        DrawItem {
+            material: MaterialHandle(material),
+            mesh: MeshHandle(mesh),
+            submesh: 0,
+            batch,
+            instances: 0..1,
+        }
+    }
+
+    #[test]
+    fn opaque_draws_group_by_material_then_mesh() {
+        let mut list = DrawList::new();
+        list.push_opaque(item(1, 1, 0));
+        list.push_opaque(item(0, 1, 1));
+        list.push_opaque(item(1, 0, 2));
+        list.push_blended(item(2, 0, 3));
+        list.push_blended(item(0, 0, 4));
+        list.sort();
+        let batches = |items: &[DrawItem]| items.iter().map(|item| item.batch).collect::<Vec<_>>();
+        assert_eq!(batches(list.opaque()), vec![1, 2, 0]);
+        assert_eq!(batches(list.blended()), vec![3, 4]);
+        list.clear();
+        assert!(list.opaque().is_empty() && list.blended().is_empty());
+    }
+
+    #[test]
+    fn interleaved_batches_are_split() {
+        let runs = back_to_front(&[
+            (3, vec![9.0, 8.0, 4.0, 1.0]),
+            (5, vec![6.0, 5.0, 2.0]),
+            (7, vec![]),
+        ]);
+        assert_eq!(
+            runs,
+            vec![(3, 0..2), (5, 0..2), (3, 2..3), (5, 2..3), (3, 3..4)]
+        );
+    }
+
+    #[test]
+    fn separate_batches_stay_whole() {
+        let runs = back_to_front(&[(0, vec![2.0, 1.0]), (1, vec![6.0, 5.0, 3.0])]);
+        assert_eq!(runs, vec![(1, 0..3), (0, 0..2)]);
+        // Equal distances keep drawing the batch already going
+        let runs = back_to_front(&[(0, vec![2.0, 1.0]), (1, vec![2.0, 2.0])]);
+        assert_eq!(runs, vec![(0, 0..1), (1, 0..2), (0, 1..2)]);
+    }
+
+    #[test]
+    fn out_of_order_instances_are_drawn_one_by_one() {
+        // Sorted for another eye, so only the last three are still back to front in the buffer
+        let runs = back_to_front(&[(2, vec![1.0, 5.0, 4.0, 3.0]), (4, vec![2.0])]);
+        assert_eq!(runs, vec![(2, 1..4), (4, 0..1), (2, 0..1)]);
+        let runs = back_to_front(&[(0, vec![1.0, 2.0, 3.0])]);
+        assert_eq!(runs, vec![(0, 2..3), (0, 1..2), (0, 0..1)]);
+    }
+}
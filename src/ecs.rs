@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+pub use hecs::{Entity, World};
+
+use crate::{
+    camera::Camera,
+    instance::InstanceAttributes,
+    light::PointLight,
+    scene::{Attachment, NodeId, SceneGraph, Transform},
+};
+
+// Transform, Camera, PointLight and InstanceAttributes are components as they are.
+// A PointLight's position is ignored, the light sits at its entity's transform.
+
+/// What the entity is called in scene files, and how the app finds the entities it animates
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Name(pub String);
+
+/// Places the entity's Transform relative to another entity's
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+/// The mesh an entity draws, an index into the renderer's meshes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MeshHandle(pub usize);
+
+/// The material an entity is drawn with, an index into the renderer's materials
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MaterialHandle(pub usize);
+
+/// Marks the Camera the window is drawn from
+#[derive(Copy, Clone, Debug)]
+pub struct MainCamera;
+
+/// Places the camera of the extra view with this index at the entity, looking down its -Z
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ViewCamera(pub usize);
+
+/// Mirrors the world into the renderer's scene graph and lights, remembering the node and
+/// light slot each entity was given so only what changed is touched from frame to frame
+#[derive(Default)]
+pub struct Extractor {
+    nodes: HashMap<Entity, NodeId>,
+    light_slots: HashMap<Entity, usize>,
+    /// Slots of despawned lights, reused before new ones are added
+    free_light_slots: Vec<usize>,
+    light_count: usize,
+}
+
+impl Extractor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn allocate_light_slot(&mut self, entity: Entity) -> usize {
+        let slot = self.free_light_slots.pop().unwrap_or_else(|| {
+            self.light_count += 1;
+            self.light_count - 1
+        });
+        self.light_slots.insert(entity, slot);
+        slot
+    }
+
+    ///Fn: Extract Scene: Gives every entity with a Transform a scene node and copies its transform and parent across
+    ///Params: batch_for picks the mesh batch for a mesh and material. Components are attached when the
+    ///Params: node is made. Returns the nodes removed with their entities, for their owners to release
+    pub fn extract_scene(
+        &mut self,
+        world: &World,
+        scene: &mut SceneGraph,
+        mut batch_for: impl FnMut(MeshHandle, MaterialHandle) -> usize,
+    ) -> Vec<(NodeId, Vec<Attachment>)> {
+        // Entities that were despawned or lost their Transform. Removing a node takes its
+        // subtree with it, whose entities get new nodes below.
+        let gone = self
+            .nodes
+            .iter()
+            .filter(|(&entity, _)| !world.satisfies::<&Transform>(entity))
+            .map(|(&entity, &node)| (entity, node))
+            .collect::<Vec<_>>();
+        let mut removed = Vec::new();
+        for (entity, node) in gone {
+            self.nodes.remove(&entity);
+            if scene.contains(node) {
+                removed.extend(scene.remove_node(node));
+            }
+        }
+        self.nodes.retain(|_, node| scene.contains(*node));
+        let nodes = &self.nodes;
+        self.light_slots
+            .retain(|entity, _| nodes.contains_key(entity));
+        for (_, attachments) in &removed {
+            for attachment in attachments {
+                if let Attachment::Light(slot) = *attachment {
+                    self.free_light_slots.push(slot);
+                }
+            }
+        }
+
+        // New entities, parents before children, so a few passes for deep hierarchies
+        loop {
+            let mut added = false;
+            let mut query = world.query::<(Entity, &Transform, Option<&Parent>)>();
+            for (entity, transform, parent) in query.iter() {
+                if self.nodes.contains_key(&entity) {
+                    continue;
+                }
+                let parent_node = match parent {
+                    Some(Parent(parent)) if world.satisfies::<&Transform>(*parent) => {
+                        match self.nodes.get(parent) {
+                            Some(&node) => Some(node),
+                            None => continue,
+                        }
+                    }
+                    _ => None,
+                };
+                let node = scene.add_node(parent_node, *transform);
+                let mut drawn = world.query_one::<(&MeshHandle, &MaterialHandle)>(entity);
+                if let Ok((&mesh, &material)) = drawn.get() {
+                    scene.attach(node, Attachment::Mesh(batch_for(mesh, material)));
+                }
+                if world.satisfies::<&PointLight>(entity) {
+                    let slot = self.allocate_light_slot(entity);
+                    scene.attach(node, Attachment::Light(slot));
+                }
+                if let Ok(view) = world.get::<&ViewCamera>(entity) {
+                    scene.attach(node, Attachment::Camera(view.0));
+                }
+                self.nodes.insert(entity, node);
+                added = true;
+            }
+            if !added {
+                break;
+            }
+        }
+
+        let mut query = world.query::<(Entity, &Transform, Option<&Parent>)>();
+        for (entity, transform, parent) in query.iter() {
+            let node = self.nodes[&entity];
+            scene.set_local(node, *transform);
+            let parent_node = parent.and_then(|Parent(parent)| self.nodes.get(parent).copied());
+            if scene.parent(node) != parent_node {
+                scene.set_parent(node, parent_node);
+            }
+        }
+        removed
+    }
+
+    ///Fn: Extract Instance Attributes: The attributes of every drawn entity, by its scene node
+    pub fn extract_instance_attributes(&self, world: &World) -> Vec<(NodeId, InstanceAttributes)> {
+        let mut query = world.query::<(Entity, &InstanceAttributes)>();
+        query
+            .iter()
+            .filter_map(|(entity, attributes)| {
+                self.nodes.get(&entity).map(|&node| (node, *attributes))
+            })
+            .collect()
+    }
+
+    ///Fn: Extract Lights: Copies each light's colour, radius and intensity into its slot
+    ///Params: Positions come from the scene graph. Returns whether anything changed
+    pub fn extract_lights(&self, world: &World, lights: &mut Vec<PointLight>) -> bool {
+        let mut changed = false;
+        if lights.len() < self.light_count {
+            lights.resize(self.light_count, PointLight::OFF);
+            changed = true;
+        }
+        let mut query = world.query::<(Entity, &PointLight)>();
+        for (entity, light) in query.iter() {
+            if let Some(&slot) = self.light_slots.get(&entity) {
+                let extracted = PointLight {
+                    position: lights[slot].position,
+                    ..*light
+                };
+                if lights[slot] != extracted {
+                    lights[slot] = extracted;
+                    changed = true;
+                }
+            }
+        }
+        for &slot in &self.free_light_slots {
+            if lights[slot].intensity != 0.0 {
+                lights[slot].intensity = 0.0;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+///Fn: Extract Main Camera: The camera marked MainCamera, None if there isn't one
+pub fn extract_main_camera(world: &World) -> Option<Camera> {
+    world
+        .query::<(&Camera, &MainCamera)>()
+        .iter()
+        .next()
+        .map(|(camera, _)| camera.clone())
+}
+
+///Fn: Find Named: The first entity with the Name, None if there isn't one
+pub fn find_named(world: &World, name: &str) -> Option<Entity> {
+    world
+        .query::<(Entity, &Name)>()
+        .iter()
+        .find(|(_, entity_name)| entity_name.0 == name)
+        .map(|(entity, _)| entity)
+}
@@ -0,0 +1,132 @@
+use crate::input::InputMap;
+#[cfg(feature = "gamepad")]
+use crate::input::{GamepadAxis, GamepadButton, GamepadEvent, InputEvent};
+
+/// Reads connected gamepads through gilrs and forwards them to the InputMap as GamepadEvents.
+/// Without the `gamepad` feature this is a stub that never reports a gamepad.
+pub struct GamepadBackend {
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
+}
+
+impl GamepadBackend {
+    #[cfg(feature = "gamepad")]
+    pub fn new() -> Self {
+        let gilrs = match gilrs::Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                log::warn!("Gamepads unavailable: {}", e);
+                None
+            }
+        };
+        if let Some(gilrs) = &gilrs {
+            for (id, gamepad) in gilrs.gamepads() {
+                log::info!("Gamepad {} connected: {}", id, gamepad.name());
+            }
+        }
+        Self { gilrs }
+    }
+
+    #[cfg(not(feature = "gamepad"))]
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    ///Fn: Poll: Forwards every gamepad event since the last poll, call once per frame
+    #[cfg(feature = "gamepad")]
+    pub fn poll(&mut self, input: &mut InputMap) {
+        let gilrs = match &mut self.gilrs {
+            Some(gilrs) => gilrs,
+            None => return,
+        };
+        while let Some(gilrs::Event { id, event, .. }) = gilrs.next_event() {
+            if let Some(event) = translate(id.into(), event) {
+                if let GamepadEvent::Connected(_) = event {
+                    log::info!("Gamepad {} connected: {}", id, gilrs.gamepad(id).name());
+                }
+                if let GamepadEvent::Disconnected(_) = event {
+                    log::info!("Gamepad {} disconnected", id);
+                }
+                input.apply(&InputEvent::Gamepad(event));
+            }
+        }
+    }
+
+    #[cfg(not(feature = "gamepad"))]
+    pub fn poll(&mut self, _input: &mut InputMap) {}
+}
+
+///Fn: Translate: Maps a gilrs event onto a GamepadEvent, None for anything unsupported
+#[cfg(feature = "gamepad")]
+fn translate(id: usize, event: gilrs::EventType) -> Option<GamepadEvent> {
+    use gilrs::EventType;
+    match event {
+        EventType::Connected => Some(GamepadEvent::Connected(id)),
+        EventType::Disconnected => Some(GamepadEvent::Disconnected(id)),
+        EventType::ButtonPressed(button, _) => Some(GamepadEvent::Button {
+            id,
+            button: translate_button(button)?,
+            pressed: true,
+        }),
+        EventType::ButtonReleased(button, _) => Some(GamepadEvent::Button {
+            id,
+            button: translate_button(button)?,
+            pressed: false,
+        }),
+        // gilrs reports the analog triggers as buttons with a value
+        EventType::ButtonChanged(gilrs::Button::LeftTrigger2, value, _) => {
+            Some(GamepadEvent::Axis {
+                id,
+                axis: GamepadAxis::LeftTrigger,
+                value,
+            })
+        }
+        EventType::ButtonChanged(gilrs::Button::RightTrigger2, value, _) => {
+            Some(GamepadEvent::Axis {
+                id,
+                axis: GamepadAxis::RightTrigger,
+                value,
+            })
+        }
+        EventType::AxisChanged(axis, value, _) => Some(GamepadEvent::Axis {
+            id,
+            axis: translate_axis(axis)?,
+            value,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn translate_button(button: gilrs::Button) -> Option<GamepadButton> {
+    use gilrs::Button;
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
+
+#[cfg(feature = "gamepad")]
+fn translate_axis(axis: gilrs::Axis) -> Option<GamepadAxis> {
+    use gilrs::Axis;
+    Some(match axis {
+        Axis::LeftStickX => GamepadAxis::LeftStickX,
+        Axis::LeftStickY => GamepadAxis::LeftStickY,
+        Axis::RightStickX => GamepadAxis::RightStickX,
+        Axis::RightStickY => GamepadAxis::RightStickY,
+        _ => return None,
+    })
+}
@@ -0,0 +1,520 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use winit::event::*;
+
+/// Where the bindings are read from, relative to the working directory
+pub const INPUT_CONFIG_PATH: &str = "config/input.ron";
+/// Used when the config file is missing or broken, so the app stays controllable
+const DEFAULT_INPUT_CONFIG: &str = include_str!("../config/input.ron");
+
+/// Identifies a connected gamepad, stable until it disconnects
+pub type GamepadId = usize;
+
+/// Gamepad buttons by position, named after an Xbox layout's sides rather than its labels
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Analog gamepad inputs, sticks from -1 to 1 with up and right positive, triggers from 0 to 1
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// Gamepad input in a backend independent form. The gamepad module produces these from
+/// real devices, and anything else can feed synthetic ones to InputMap::apply as InputEvent::Gamepad.
+/// Gamepads that were plugged in before startup appear with their first button or axis event.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GamepadEvent {
+    Connected(GamepadId),
+    Disconnected(GamepadId),
+    Button {
+        id: GamepadId,
+        button: GamepadButton,
+        pressed: bool,
+    },
+    Axis {
+        id: GamepadId,
+        axis: GamepadAxis,
+        value: f32,
+    },
+}
+
+/// The current state of one connected gamepad
+#[derive(Clone, Debug, Default)]
+struct Gamepad {
+    buttons: HashSet<GamepadButton>,
+    axes: HashMap<GamepadAxis, f32>,
+}
+
+impl Gamepad {
+    ///Fn: Axis: The axis value with the deadzone removed and the rest rescaled to the full range
+    ///Params: Sticks use a radial deadzone over both their axes, so diagonals aren't snapped
+    fn axis(&self, axis: GamepadAxis, deadzone: f32) -> f32 {
+        let raw = |axis| self.axes.get(&axis).copied().unwrap_or(0.0);
+        let stick = match axis {
+            GamepadAxis::LeftStickX | GamepadAxis::LeftStickY => {
+                Some((GamepadAxis::LeftStickX, GamepadAxis::LeftStickY))
+            }
+            GamepadAxis::RightStickX | GamepadAxis::RightStickY => {
+                Some((GamepadAxis::RightStickX, GamepadAxis::RightStickY))
+            }
+            GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => None,
+        };
+        let magnitude = match stick {
+            Some((x, y)) => raw(x).hypot(raw(y)),
+            None => raw(axis).abs(),
+        };
+        if magnitude <= deadzone {
+            return 0.0;
+        }
+        let rescaled = ((magnitude - deadzone) / (1.0 - deadzone)).min(1.0);
+        raw(axis) / magnitude * rescaled
+    }
+}
+
+/// Anything that is either held or not
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    /// Held while it's held on any connected gamepad
+    Gamepad(GamepadButton),
+}
+
+/// One input feeding an axis, multiplied by its scale
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AxisBinding {
+    /// 1 while positive is held, -1 while negative is held
+    Buttons {
+        positive: Button,
+        negative: Button,
+    },
+    /// Pixels of mouse motion this frame
+    MouseX(f32),
+    MouseY(f32),
+    /// Lines scrolled this frame, up is positive
+    Scroll(f32),
+    /// Summed over every connected gamepad, after the deadzone
+    Gamepad(GamepadAxis, f32),
+}
+
+/// A single change to the input state, from the window, raw mouse motion or a gamepad.
+/// Everything reaching the InputMap goes through these, so they can be recorded and replayed.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Button {
+        button: Button,
+        pressed: bool,
+    },
+    /// Pixels of raw mouse motion
+    MouseMotion(f32, f32),
+    /// Lines scrolled, up is positive
+    Scroll(f32),
+    /// Releases while unfocused never arrive, so everything held is let go
+    FocusLost,
+    Gamepad(GamepadEvent),
+}
+
+impl InputEvent {
+    ///Fn: From Window Event: Keys, mouse buttons, scrolling and focus loss, None for anything else
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        let button_event = |button, state| InputEvent::Button {
+            button,
+            pressed: state == ElementState::Pressed,
+        };
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => Some(button_event(Button::Key(*keycode), *state)),
+            WindowEvent::MouseInput { state, button, .. } => {
+                Some(button_event(Button::Mouse(*button), *state))
+            }
+            WindowEvent::MouseWheel { delta, .. } => Some(InputEvent::Scroll(match delta {
+                MouseScrollDelta::LineDelta(_, y) => *y,
+                // Roughly one line per 100 pixels of trackpad scrolling
+                MouseScrollDelta::PixelDelta(position) => position.y as f32 / 100.0,
+            })),
+            WindowEvent::Focused(false) => Some(InputEvent::FocusLost),
+            _ => None,
+        }
+    }
+
+    ///Fn: From Device Event: Raw mouse motion, which isn't limited by the window edges
+    pub fn from_device_event(event: &DeviceEvent) -> Option<Self> {
+        match event {
+            DeviceEvent::MouseMotion { delta } => {
+                Some(InputEvent::MouseMotion(delta.0 as f32, delta.1 as f32))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Named actions and axes with everything bound to them, as stored in the config file
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct InputBindings {
+    pub actions: HashMap<String, Vec<Button>>,
+    pub axes: HashMap<String, Vec<AxisBinding>>,
+    /// Stick and trigger travel ignored around rest, from 0 to 1
+    #[serde(default = "default_gamepad_deadzone")]
+    pub gamepad_deadzone: f32,
+}
+
+fn default_gamepad_deadzone() -> f32 {
+    0.15
+}
+
+impl InputBindings {
+    pub fn from_ron(source: &str) -> Result<Self> {
+        ron::de::from_str(source).context("Couldn't parse input bindings")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        Self::from_ron(&source)
+    }
+
+    ///Fn: Load Or Default: The bindings at path, falling back to the built in defaults
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        Self::load(path).unwrap_or_else(|e| {
+            log::warn!("Using the default input bindings: {:?}", e);
+            Self::from_ron(DEFAULT_INPUT_CONFIG).expect("default input bindings are valid")
+        })
+    }
+}
+
+/// Tracks the state of every button and this frame's mouse movement, and answers
+/// queries by action or axis name. Unknown names are never pressed and read as zero.
+pub struct InputMap {
+    pub bindings: InputBindings,
+    held: HashSet<Button>,
+    /// Buttons that went down since the last end_frame
+    pressed: HashSet<Button>,
+    /// Ordered, so axes sum in the same order on every run and replays match exactly
+    gamepads: BTreeMap<GamepadId, Gamepad>,
+    mouse_motion: (f32, f32),
+    scroll: f32,
+    /// Everything applied since the last end_frame, in order
+    frame_events: Vec<InputEvent>,
+}
+
+impl InputMap {
+    pub fn new(bindings: InputBindings) -> Self {
+        Self {
+            bindings,
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            gamepads: BTreeMap::new(),
+            mouse_motion: (0.0, 0.0),
+            scroll: 0.0,
+            frame_events: Vec::new(),
+        }
+    }
+
+    ///Fn: Apply: Updates the input state with one event, real, replayed or synthetic
+    pub fn apply(&mut self, event: &InputEvent) {
+        self.frame_events.push(*event);
+        match *event {
+            InputEvent::Button { button, pressed } => self.set_button(button, pressed),
+            InputEvent::MouseMotion(x, y) => {
+                self.mouse_motion.0 += x;
+                self.mouse_motion.1 += y;
+            }
+            InputEvent::Scroll(lines) => self.scroll += lines,
+            InputEvent::FocusLost => self.held.clear(),
+            InputEvent::Gamepad(event) => self.apply_gamepad_event(event),
+        }
+    }
+
+    fn apply_gamepad_event(&mut self, event: GamepadEvent) {
+        match event {
+            GamepadEvent::Connected(id) => {
+                self.gamepads.entry(id).or_default();
+            }
+            // Whatever it was holding is released with it
+            GamepadEvent::Disconnected(id) => {
+                self.gamepads.remove(&id);
+            }
+            GamepadEvent::Button {
+                id,
+                button,
+                pressed,
+            } => {
+                let gamepad = self.gamepads.entry(id).or_default();
+                if !pressed {
+                    gamepad.buttons.remove(&button);
+                } else if gamepad.buttons.insert(button) {
+                    self.pressed.insert(Button::Gamepad(button));
+                }
+            }
+            GamepadEvent::Axis { id, axis, value } => {
+                self.gamepads
+                    .entry(id)
+                    .or_default()
+                    .axes
+                    .insert(axis, value);
+            }
+        }
+    }
+
+    fn is_held(&self, button: Button) -> bool {
+        match button {
+            Button::Gamepad(button) => self
+                .gamepads
+                .values()
+                .any(|gamepad| gamepad.buttons.contains(&button)),
+            _ => self.held.contains(&button),
+        }
+    }
+
+    fn set_button(&mut self, button: Button, pressed: bool) {
+        if let Button::Gamepad(button) = button {
+            // Gamepad buttons are tracked per gamepad, this is one that came from nowhere
+            log::warn!("Gamepad button {:?} without a gamepad", button);
+            return;
+        }
+        // Key repeat sends more presses while held, which mustn't count as new presses
+        if !pressed {
+            self.held.remove(&button);
+        } else if self.held.insert(button) {
+            self.pressed.insert(button);
+        }
+    }
+
+    fn action_buttons(&self, action: &str) -> &[Button] {
+        self.bindings
+            .actions
+            .get(action)
+            .map_or(&[], |buttons| buttons.as_slice())
+    }
+
+    ///Fn: Is Pressed: Whether any button bound to the action is held
+    pub fn is_pressed(&self, action: &str) -> bool {
+        self.action_buttons(action)
+            .iter()
+            .any(|&button| self.is_held(button))
+    }
+
+    ///Fn: Just Pressed: Whether a button bound to the action went down this frame
+    pub fn just_pressed(&self, action: &str) -> bool {
+        self.action_buttons(action)
+            .iter()
+            .any(|button| self.pressed.contains(button))
+    }
+
+    ///Fn: Axis Value: The sum of everything bound to the axis this frame
+    pub fn axis_value(&self, axis: &str) -> f32 {
+        let bindings = match self.bindings.axes.get(axis) {
+            Some(bindings) => bindings,
+            None => return 0.0,
+        };
+        let button_value = |button| self.is_held(button) as i32 as f32;
+        bindings
+            .iter()
+            .map(|binding| match *binding {
+                AxisBinding::Buttons { positive, negative } => {
+                    button_value(positive) - button_value(negative)
+                }
+                AxisBinding::MouseX(scale) => self.mouse_motion.0 * scale,
+                AxisBinding::MouseY(scale) => self.mouse_motion.1 * scale,
+                AxisBinding::Scroll(scale) => self.scroll * scale,
+                AxisBinding::Gamepad(axis, scale) => {
+                    let deadzone = self.bindings.gamepad_deadzone;
+                    self.gamepads
+                        .values()
+                        .map(|gamepad| gamepad.axis(axis, deadzone))
+                        .sum::<f32>()
+                        * scale
+                }
+            })
+            .sum()
+    }
+
+    ///Fn: End Frame: Forgets this frame's presses and motion, call once everything has read them
+    ///Params: Returns the events applied during the frame, for recording
+    pub fn end_frame(&mut self) -> Vec<InputEvent> {
+        self.pressed.clear();
+        self.mouse_motion = (0.0, 0.0);
+        self.scroll = 0.0;
+        std::mem::take(&mut self.frame_events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINDINGS: &str = r#"(
+        gamepad_deadzone: 0.2,
+        actions: {
+            "jump": [Key(Space), Gamepad(South)],
+        },
+        axes: {
+            "move_x": [Gamepad(LeftStickX, 1.0)],
+            "move_y": [Gamepad(RightTrigger, 1.0), Gamepad(LeftTrigger, -1.0)],
+        },
+    )"#;
+
+    fn input_map() -> InputMap {
+        InputMap::new(InputBindings::from_ron(BINDINGS).unwrap())
+    }
+
+    fn axis(id: GamepadId, axis: GamepadAxis, value: f32) -> InputEvent {
+        InputEvent::Gamepad(GamepadEvent::Axis { id, axis, value })
+    }
+
+    fn button(id: GamepadId, button: GamepadButton, pressed: bool) -> InputEvent {
+        InputEvent::Gamepad(GamepadEvent::Button {
+            id,
+            button,
+            pressed,
+        })
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn axes_rescale_past_the_deadzone() {
+        let mut input = input_map();
+        input.apply(&InputEvent::Gamepad(GamepadEvent::Connected(0)));
+
+        input.apply(&axis(0, GamepadAxis::RightTrigger, 0.15));
+        assert_near(input.axis_value("move_y"), 0.0);
+        input.apply(&axis(0, GamepadAxis::RightTrigger, 0.6));
+        assert_near(input.axis_value("move_y"), 0.5);
+        input.apply(&axis(0, GamepadAxis::LeftTrigger, 1.0));
+        assert_near(input.axis_value("move_y"), -0.5);
+
+        input.apply(&axis(0, GamepadAxis::LeftStickX, -0.6));
+        assert_near(input.axis_value("move_x"), -0.5);
+        // The stick's deadzone is radial, so pushing the other axis brings this one out of it too
+        input.apply(&axis(0, GamepadAxis::LeftStickX, 0.12));
+        assert_near(input.axis_value("move_x"), 0.0);
+        input.apply(&axis(0, GamepadAxis::LeftStickY, 0.6));
+        let magnitude = 0.12f32.hypot(0.6);
+        assert_near(
+            input.axis_value("move_x"),
+            0.12 / magnitude * (magnitude - 0.2) / 0.8,
+        );
+    }
+
+    #[test]
+    fn axes_sum_over_gamepads() {
+        let mut input = input_map();
+        input.apply(&axis(0, GamepadAxis::LeftStickX, 0.6));
+        input.apply(&axis(1, GamepadAxis::LeftStickX, 1.0));
+        assert_near(input.axis_value("move_x"), 1.5);
+    }
+
+    #[test]
+    fn buttons_press_once_until_released() {
+        let mut input = input_map();
+        input.apply(&InputEvent::Gamepad(GamepadEvent::Connected(0)));
+        assert!(!input.is_pressed("jump"));
+
+        input.apply(&button(0, GamepadButton::South, true));
+        assert!(input.is_pressed("jump"));
+        assert!(input.just_pressed("jump"));
+
+        input.end_frame();
+        assert!(input.is_pressed("jump"));
+        assert!(!input.just_pressed("jump"));
+
+        // Another press while held isn't a new press
+        input.apply(&button(0, GamepadButton::South, true));
+        assert!(!input.just_pressed("jump"));
+
+        input.apply(&button(0, GamepadButton::South, false));
+        assert!(!input.is_pressed("jump"));
+        input.end_frame();
+
+        input.apply(&button(0, GamepadButton::South, true));
+        assert!(input.just_pressed("jump"));
+    }
+
+    #[test]
+    fn buttons_are_held_while_any_gamepad_holds_them() {
+        let mut input = input_map();
+        input.apply(&button(0, GamepadButton::South, true));
+        input.apply(&button(1, GamepadButton::South, true));
+        input.apply(&button(0, GamepadButton::South, false));
+        assert!(input.is_pressed("jump"));
+        input.apply(&button(1, GamepadButton::South, false));
+        assert!(!input.is_pressed("jump"));
+    }
+
+    #[test]
+    fn disconnecting_resets_the_gamepad() {
+        let mut input = input_map();
+        input.apply(&InputEvent::Gamepad(GamepadEvent::Connected(0)));
+        input.apply(&axis(0, GamepadAxis::LeftStickX, 1.0));
+        input.apply(&axis(0, GamepadAxis::RightTrigger, 1.0));
+        input.apply(&button(0, GamepadButton::South, true));
+        input.end_frame();
+
+        input.apply(&InputEvent::Gamepad(GamepadEvent::Disconnected(0)));
+        assert_near(input.axis_value("move_x"), 0.0);
+        assert_near(input.axis_value("move_y"), 0.0);
+        assert!(!input.is_pressed("jump"));
+
+        // Reconnecting starts from rest rather than where it was left
+        input.apply(&InputEvent::Gamepad(GamepadEvent::Connected(0)));
+        assert_near(input.axis_value("move_x"), 0.0);
+        assert!(!input.is_pressed("jump"));
+    }
+
+    #[test]
+    fn events_are_returned_for_recording() {
+        let mut input = input_map();
+        let events = [
+            InputEvent::Gamepad(GamepadEvent::Connected(0)),
+            button(0, GamepadButton::South, true),
+            axis(0, GamepadAxis::LeftStickX, 0.5),
+        ];
+        for event in &events {
+            input.apply(event);
+        }
+        assert_eq!(input.end_frame(), events);
+        assert!(input.end_frame().is_empty());
+    }
+}
@@ -0,0 +1,488 @@
+use std::{cmp::Ordering, collections::HashMap, mem, ops::Range};
+
+use glam::Mat4;
+use serde::{Deserialize, Serialize};
+
+use crate::{bundle::Assets, scene::NodeId};
+
+/// Declares InstanceRaw and, from the same list of fields, its vertex attributes and the WGSL
+/// InstanceInput struct the shaders read it through, so the three can't drift apart.
+/// Each field gives its shader location, vertex format and WGSL type, and must be the
+/// format's size so the struct packs the way the attributes are laid out.
+macro_rules! instance_layout {
+    ($($(#[doc = $doc:literal])* $field:ident: $ty:ty => ($location:literal, $format:ident, $wgsl:literal),)*) => {
+        #[repr(C)]
+        #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+        pub struct InstanceRaw {
+            $($(#[doc = $doc])* $field: $ty,)*
+        }
+
+        $(const _: () = assert!(
+            mem::size_of::<$ty>() as u64 == wgpu::VertexFormat::$format.size(),
+            concat!("InstanceRaw::", stringify!($field), " doesn't match its vertex format"),
+        );)*
+
+        impl InstanceRaw {
+            const ATTRIBUTES: &'static [wgpu::VertexAttribute] =
+                &wgpu::vertex_attr_array![$($location => $format,)*];
+            /// Location, name and WGSL type of each field
+            const WGSL_FIELDS: &'static [(u32, &'static str, &'static str)] =
+                &[$(($location, stringify!($field), $wgsl),)*];
+        }
+    };
+}
+
+// Locations 0 to 4 are left for per vertex data
+instance_layout! {
+    /// Columns of the world matrix, which carries the node's non-uniform scale
+    model_matrix_0: [f32; 4] => (5, Float32x4, "vec4<f32>"),
+    model_matrix_1: [f32; 4] => (6, Float32x4, "vec4<f32>"),
+    model_matrix_2: [f32; 4] => (7, Float32x4, "vec4<f32>"),
+    model_matrix_3: [f32; 4] => (8, Float32x4, "vec4<f32>"),
+    tint: [f32; 4] => (9, Float32x4, "vec4<f32>"),
+    layer: u32 => (10, Uint32, "u32"),
+    custom: [f32; 4] => (11, Float32x4, "vec4<f32>"),
+}
+
+impl InstanceRaw {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            // We need to switch from using a step mode of Vertex to Instance
+            // This means that our shaders will only change to use the next
+            // instance when the shader starts processing a new instance
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: Self::ATTRIBUTES,
+        }
+    }
+
+    ///Fn: WGSL: The InstanceInput struct matching desc, to put in front of the shaders that draw instances
+    ///Params: Followed by the helpers in instance.wgsl, which read the model matrix and normals from it
+    pub fn wgsl(assets: &Assets) -> String {
+        let mut source = String::from("struct InstanceInput {\n");
+        for (location, name, ty) in Self::WGSL_FIELDS {
+            source.push_str(&format!(
+                "    [[location({})]] {}: {};\n",
+                location, name, ty
+            ));
+        }
+        source.push_str("};\n\n");
+        source.push_str(&assets.shader("instance.wgsl", include_str!("../shader/instance.wgsl")));
+        source
+    }
+}
+
+/// Per instance shading inputs, which stay with the instance as its node moves
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstanceAttributes {
+    /// Multiplies the material's tint
+    pub tint: [f32; 4],
+    /// Texture array layer or material index, for shaders that pick by instance
+    pub layer: u32,
+    /// Free for custom shaders
+    pub custom: [f32; 4],
+}
+
+impl Default for InstanceAttributes {
+    fn default() -> Self {
+        Self {
+            tint: [1.0; 4],
+            layer: 0,
+            custom: [0.0; 4],
+        }
+    }
+}
+
+/// One draw of a mesh batch, placed by its scene node's world matrix
+#[derive(Copy, Clone, Debug)]
+pub struct Instance {
+    pub model: Mat4,
+    pub attributes: InstanceAttributes,
+}
+
+impl Instance {
+    pub fn to_raw(self) -> InstanceRaw {
+        let [model_matrix_0, model_matrix_1, model_matrix_2, model_matrix_3] =
+            self.model.to_cols_array_2d();
+        InstanceRaw {
+            model_matrix_0,
+            model_matrix_1,
+            model_matrix_2,
+            model_matrix_3,
+            tint: self.attributes.tint,
+            layer: self.attributes.layer,
+            custom: self.attributes.custom,
+        }
+    }
+}
+
+/// Instances the smallest buffer has room for, so small batches don't regrow straight away
+const MIN_CAPACITY: usize = 16;
+
+/// The instances of one mesh batch, each owned by a scene node, mirrored into a vertex buffer.
+/// Changes are collected and uploaded once a frame, only the range that changed unless the
+/// buffer had to grow to fit them.
+pub struct InstanceBuffer {
+    label: String,
+    slots: InstanceSlots,
+    buffer: wgpu::Buffer,
+    /// Instances the buffer has room for
+    capacity: usize,
+}
+
+impl InstanceBuffer {
+    ///Fn: New: Creates a buffer holding the instances, which go up with the first upload
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        instances: impl IntoIterator<Item = (NodeId, Instance)>,
+    ) -> Self {
+        let slots = InstanceSlots::new(instances);
+        let capacity = slots.instances.len().max(MIN_CAPACITY).next_power_of_two();
+        Self {
+            label: label.to_string(),
+            buffer: create_buffer(device, label, capacity),
+            slots,
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.instances.is_empty()
+    }
+
+    ///Fn: Instances: In buffer order
+    pub fn instances(&self) -> &[Instance] {
+        &self.slots.instances
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    ///Fn: Set Model: Moves the node's instance, adding one with default attributes if it has none
+    pub fn set_model(&mut self, node: NodeId, model: Mat4) {
+        self.slots.set_model(node, model);
+    }
+
+    ///Fn: Set Attributes: Changes the node's instance attributes, false if it has no instance
+    pub fn set_attributes(&mut self, node: NodeId, attributes: InstanceAttributes) -> bool {
+        self.slots.set_attributes(node, attributes)
+    }
+
+    ///Fn: Remove: Removes the node's instance, moving the last instance into its slot
+    pub fn remove(&mut self, node: NodeId) -> Option<Instance> {
+        self.slots.remove(node)
+    }
+
+    ///Fn: Sort By: Reorders the instances, uploading them again only if the order changed
+    pub fn sort_by(&mut self, compare: impl FnMut(&Instance, &Instance) -> Ordering) {
+        self.slots.sort_by(compare);
+    }
+
+    ///Fn: Upload: Writes the changed instances, growing the buffer first if they no longer fit
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let upload = match self.slots.take_upload(self.capacity) {
+            Some(upload) => upload,
+            None => return,
+        };
+        if let Some(capacity) = upload.grow_to {
+            self.capacity = capacity;
+            self.buffer = create_buffer(device, &self.label, self.capacity);
+            log::debug!("Grew {} to {} instances", self.label, self.capacity);
+        }
+        let instance_data = self.slots.instances[upload.range.clone()]
+            .iter()
+            .map(|instance| instance.to_raw())
+            .collect::<Vec<_>>();
+        queue.write_buffer(
+            &self.buffer,
+            (upload.range.start * mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            bytemuck::cast_slice(&instance_data),
+        );
+    }
+}
+
+/// What an upload has to do, worked out by InstanceSlots::take_upload
+#[derive(Clone, Debug, PartialEq)]
+struct Upload {
+    /// The capacity to recreate the buffer at, when the instances no longer fit
+    grow_to: Option<usize>,
+    /// The instances to write
+    range: Range<usize>,
+}
+
+/// An InstanceBuffer's instances with the node owning each and the slots changed since the
+/// last upload, kept apart from the buffer itself
+#[derive(Default)]
+struct InstanceSlots {
+    instances: Vec<Instance>,
+    /// Node owning each instance, parallel to instances
+    nodes: Vec<NodeId>,
+    by_node: HashMap<NodeId, usize>,
+    /// Slots changed since the last upload. Removals mark the slot they empty at the end,
+    /// so the range can run past the instances that are left.
+    dirty: Option<Range<usize>>,
+}
+
+impl InstanceSlots {
+    fn new(instances: impl IntoIterator<Item = (NodeId, Instance)>) -> Self {
+        let (nodes, instances): (Vec<_>, Vec<_>) = instances.into_iter().unzip();
+        let by_node = nodes
+            .iter()
+            .enumerate()
+            .map(|(slot, &node)| (node, slot))
+            .collect();
+        let dirty = if instances.is_empty() {
+            None
+        } else {
+            Some(0..instances.len())
+        };
+        Self {
+            instances,
+            nodes,
+            by_node,
+            dirty,
+        }
+    }
+
+    fn set_model(&mut self, node: NodeId, model: Mat4) {
+        let attributes = match self.by_node.get(&node) {
+            Some(&slot) => self.instances[slot].attributes,
+            None => InstanceAttributes::default(),
+        };
+        self.insert(node, Instance { model, attributes });
+    }
+
+    fn set_attributes(&mut self, node: NodeId, attributes: InstanceAttributes) -> bool {
+        match self.by_node.get(&node) {
+            Some(&slot) => {
+                if self.instances[slot].attributes != attributes {
+                    self.instances[slot].attributes = attributes;
+                    self.mark_dirty(slot);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    ///Fn: Insert: Adds the node's instance, or replaces it if the node already has one
+    fn insert(&mut self, node: NodeId, instance: Instance) {
+        let slot = match self.by_node.get(&node) {
+            Some(&slot) => {
+                self.instances[slot] = instance;
+                slot
+            }
+            None => {
+                self.instances.push(instance);
+                self.nodes.push(node);
+                self.by_node.insert(node, self.instances.len() - 1);
+                self.instances.len() - 1
+            }
+        };
+        self.mark_dirty(slot);
+    }
+
+    fn remove(&mut self, node: NodeId) -> Option<Instance> {
+        let slot = self.by_node.remove(&node)?;
+        let instance = self.instances.swap_remove(slot);
+        self.nodes.swap_remove(slot);
+        if slot < self.instances.len() {
+            self.by_node.insert(self.nodes[slot], slot);
+            self.mark_dirty(slot);
+        }
+        // The slot the last instance moved out of, which no longer holds anything to draw
+        self.mark_dirty(self.instances.len());
+        Some(instance)
+    }
+
+    fn sort_by(&mut self, mut compare: impl FnMut(&Instance, &Instance) -> Ordering) {
+        let mut order = (0..self.instances.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| compare(&self.instances[a], &self.instances[b]));
+        if order.iter().enumerate().all(|(slot, &index)| slot == index) {
+            return;
+        }
+        self.instances = order.iter().map(|&index| self.instances[index]).collect();
+        self.nodes = order.iter().map(|&index| self.nodes[index]).collect();
+        for (slot, &node) in self.nodes.iter().enumerate() {
+            self.by_node.insert(node, slot);
+        }
+        self.dirty = Some(0..self.instances.len());
+    }
+
+    fn mark_dirty(&mut self, slot: usize) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(range) => range.start.min(slot)..range.end.max(slot + 1),
+            None => slot..slot + 1,
+        });
+    }
+
+    ///Fn: Take Upload: What has to be written for the buffer to match, clearing the changes
+    ///Params: capacity is the instances the buffer has room for. None when there's nothing to write
+    fn take_upload(&mut self, capacity: usize) -> Option<Upload> {
+        let dirty = self.dirty.take();
+        let len = self.instances.len();
+        if len > capacity {
+            // The new buffer starts out empty, so everything goes up
+            return Some(Upload {
+                grow_to: Some(len.next_power_of_two()),
+                range: 0..len,
+            });
+        }
+        // Slots emptied at the end don't need writing, nothing draws them
+        let range = dirty.map(|range| range.start.min(len)..range.end.min(len))?;
+        if range.is_empty() {
+            return None;
+        }
+        Some(Upload {
+            grow_to: None,
+            range,
+        })
+    }
+}
+
+fn create_buffer(device: &wgpu::Device, label: &str, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: (capacity * mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{SceneGraph, Transform};
+
+    fn nodes(count: usize) -> Vec<NodeId> {
+        let mut graph = SceneGraph::new();
+        (0..count)
+            .map(|_| graph.add_node(None, Transform::IDENTITY))
+            .collect()
+    }
+
+    fn at(x: f32) -> Mat4 {
+        Mat4::from_translation(glam::Vec3::X * x)
+    }
+
+    fn xs(slots: &InstanceSlots) -> Vec<f32> {
+        slots
+            .instances
+            .iter()
+            .map(|instance| instance.model.w_axis.x)
+            .collect()
+    }
+
+    fn upload(range: Range<usize>) -> Option<Upload> {
+        Some(Upload {
+            grow_to: None,
+            range,
+        })
+    }
+
+    #[test]
+    fn removing_from_the_middle_moves_the_last_instance() {
+        let nodes = nodes(5);
+        let mut slots = InstanceSlots::default();
+        for (i, &node) in nodes.iter().enumerate() {
+            slots.set_model(node, at(i as f32));
+        }
+        assert_eq!(slots.take_upload(MIN_CAPACITY), upload(0..5));
+        assert_eq!(slots.take_upload(MIN_CAPACITY), None);
+
+        assert!(slots.remove(nodes[1]).is_some());
+        assert!(slots.remove(nodes[1]).is_none());
+        assert_eq!(xs(&slots), vec![0.0, 4.0, 2.0, 3.0]);
+        // Both the slot the last instance moved into and the one it left
+        assert_eq!(slots.dirty, Some(1..5));
+        // Only the moved instance is left to write
+        assert_eq!(slots.take_upload(MIN_CAPACITY), upload(1..4));
+
+        // The moved instance is still found through its node
+        slots.set_model(nodes[4], at(7.0));
+        assert_eq!(xs(&slots), vec![0.0, 7.0, 2.0, 3.0]);
+        assert_eq!(slots.take_upload(MIN_CAPACITY), upload(1..2));
+    }
+
+    #[test]
+    fn removing_the_last_instance_writes_nothing() {
+        let nodes = nodes(3);
+        let mut slots = InstanceSlots::new(nodes.iter().map(|&node| {
+            (
+                node,
+                Instance {
+                    model: Mat4::IDENTITY,
+                    attributes: InstanceAttributes::default(),
+                },
+            )
+        }));
+        assert_eq!(slots.take_upload(MIN_CAPACITY), upload(0..3));
+        slots.remove(nodes[2]);
+        assert_eq!(slots.dirty, Some(2..3));
+        assert_eq!(slots.take_upload(MIN_CAPACITY), None);
+        assert_eq!(slots.dirty, None);
+    }
+
+    #[test]
+    fn growing_uploads_everything() {
+        let nodes = nodes(MIN_CAPACITY + 1);
+        let mut slots = InstanceSlots::default();
+        for (i, &node) in nodes[..MIN_CAPACITY].iter().enumerate() {
+            slots.set_model(node, at(i as f32));
+        }
+        assert_eq!(slots.take_upload(MIN_CAPACITY), upload(0..MIN_CAPACITY));
+
+        // One more doesn't fit, so the new buffer needs every instance and not just the new one
+        slots.set_model(nodes[MIN_CAPACITY], at(-1.0));
+        assert_eq!(slots.dirty, Some(MIN_CAPACITY..MIN_CAPACITY + 1));
+        assert_eq!(
+            slots.take_upload(MIN_CAPACITY),
+            Some(Upload {
+                grow_to: Some(MIN_CAPACITY * 2),
+                range: 0..MIN_CAPACITY + 1,
+            })
+        );
+        assert_eq!(slots.take_upload(MIN_CAPACITY * 2), None);
+    }
+
+    #[test]
+    fn only_changes_are_uploaded() {
+        let nodes = nodes(4);
+        let mut slots = InstanceSlots::default();
+        for (i, &node) in nodes.iter().enumerate() {
+            slots.set_model(node, at(i as f32));
+        }
+        slots.take_upload(MIN_CAPACITY);
+
+        let attributes = InstanceAttributes {
+            layer: 2,
+            ..InstanceAttributes::default()
+        };
+        assert!(slots.set_attributes(nodes[2], attributes));
+        assert_eq!(slots.take_upload(MIN_CAPACITY), upload(2..3));
+        // Unchanged attributes don't need writing again
+        assert!(slots.set_attributes(nodes[2], attributes));
+        assert_eq!(slots.take_upload(MIN_CAPACITY), None);
+
+        // Moving keeps the attributes
+        slots.set_model(nodes[2], at(9.0));
+        assert_eq!(slots.instances[2].attributes, attributes);
+        slots.set_model(nodes[0], at(8.0));
+        assert_eq!(slots.take_upload(MIN_CAPACITY), upload(0..3));
+
+        // Sorting into the order it's already in changes nothing
+        slots.sort_by(|_, _| Ordering::Equal);
+        assert_eq!(slots.take_upload(MIN_CAPACITY), None);
+        slots.sort_by(|a, b| a.model.w_axis.x.total_cmp(&b.model.w_axis.x));
+        assert_eq!(xs(&slots), vec![1.0, 3.0, 8.0, 9.0]);
+        assert_eq!(slots.take_upload(MIN_CAPACITY), upload(0..4));
+        assert_eq!(slots.by_node[&nodes[0]], 2);
+    }
+}
@@ -0,0 +1,67 @@
+use glam::Vec3;
+
+/// Lights the light buffer has room for, the bind groups reading it can't follow a bigger one
+pub const MAX_LIGHTS: usize = 4096;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    /// Distance at which the light has faded out completely
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+// Catches the Rust layout drifting from the Lights stride in shader/cluster_common.wgsl
+const _: () = assert!(std::mem::size_of::<PointLight>() == 32);
+
+impl PointLight {
+    /// Fills unused slots, reaching nothing
+    pub const OFF: PointLight = PointLight {
+        position: [0.0; 3],
+        radius: 0.0,
+        color: [0.0; 3],
+        intensity: 0.0,
+    };
+
+    pub fn new(position: Vec3, radius: f32, color: Vec3, intensity: f32) -> Self {
+        Self {
+            position: position.into(),
+            radius,
+            color: color.into(),
+            intensity,
+        }
+    }
+
+    ///Fn: Desc: Lights as per instance vertex data, for passes that draw one volume per light
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<PointLight>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+///Fn: Create Light Buffer: Makes room for MAX_LIGHTS lights, read as vertices or storage
+pub fn create_light_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Light Buffer"),
+        size: (MAX_LIGHTS * std::mem::size_of::<PointLight>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
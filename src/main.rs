@@ -1,13 +1,58 @@
+mod antialiasing;
+mod bundle;
 mod camera;
+mod camera_controller;
+mod camera_path;
+mod cluster;
+mod deferred;
+mod draw_list;
+mod ecs;
+mod gamepad;
+mod input;
+mod instance;
+mod light;
+mod material;
+mod mesh;
+mod oit;
 mod pipeline;
 mod renderer;
+mod replay;
+mod scene;
+mod scene_file;
+mod ssao;
 mod texture;
+mod timer;
+mod view;
 
-use camera::Camera;
+use antialiasing::{AntiAliasing, PostAntiAliasing};
+use bundle::{Assets, Bundle};
+use camera::{Camera, CameraUniform, DepthMode, Projection};
+use camera_controller::{CameraController, CameraMode};
+use camera_path::{CameraPath, CameraPathPlayer, Keyframe};
+use cluster::ClusteredLighting;
+use deferred::Deferred;
+use draw_list::{DrawItem, DrawList};
+use ecs::{Entity, Extractor, MainCamera, MaterialHandle, MeshHandle, Parent, World};
 use futures::executor::block_on;
+use gamepad::GamepadBackend;
+use input::{InputBindings, InputEvent, InputMap};
+use instance::{Instance, InstanceAttributes, InstanceBuffer, InstanceRaw};
+use light::PointLight;
+use material::{AlphaMode, Material};
+use mesh::{Mesh, MeshData, Vertex};
+use oit::WeightedBlendedOit;
+use renderer::{RenderPath, TransparencyMode};
+use replay::{RecordedFrame, Recording, Replay};
+use scene::{Attachment, SceneGraph, Transform};
+use scene_file::{
+    CameraDesc, Layer, LightDesc, MaterialDesc, MeshDesc, NodeDesc, SceneFile, TextureRef,
+};
+use ssao::Ssao;
 use std::{borrow::Cow, iter};
+use timer::{FrameTime, FrameTimer};
+use view::{View, ViewCompositor, ViewDescriptor, ViewTarget, Viewport};
 
-use glam::{Mat4, Quat, Vec3};
+use glam::{Quat, Vec3};
 use wgpu::util::DeviceExt;
 use winit::{
     event::*,
@@ -17,283 +62,439 @@ use winit::{
 
 pub const APP_NAME: &str = "Ashen";
 pub const WINDOW_SIZE: [u32; 2] = [1024, 792];
+/// Seconds per simulation step when the fixed timestep is on
+const FIXED_TIMESTEP: f32 = 1.0 / 60.0;
+
+/// Where finished frames go
+enum Output {
+    Window {
+        surface: wgpu::Surface,
+        swap_chain: wgpu::SwapChain,
+    },
+    /// Headless, frames are rendered into a texture that is never shown
+    Offscreen(texture::Texture),
+}
 
 struct State {
-    surface: wgpu::Surface,
+    output: Output,
     device: wgpu::Device,
     queue: wgpu::Queue,
     swap_chain_desc: wgpu::SwapChainDescriptor,
-    swap_chain: wgpu::SwapChain,
     size: winit::dpi::PhysicalSize<u32>,
+    draw_module: wgpu::ShaderModule,
+    render_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
-    #[allow(dead_code)]
-    diffuse_texture: texture::Texture,
-    diffuse_bind_group: wgpu::BindGroup,
+    single_sample_render_pipeline: wgpu::RenderPipeline,
+    depth_pipeline: wgpu::RenderPipeline,
+    transparent_pipeline: wgpu::RenderPipeline,
+    single_sample_transparent_pipeline: wgpu::RenderPipeline,
+    transparency: TransparencyMode,
+    oit: WeightedBlendedOit,
+    /// Indexed by MeshHandle
+    meshes: Vec<Mesh>,
+    /// Indexed by MaterialHandle
+    materials: Vec<MaterialEntry>,
+    /// The main camera as extracted from the world in the last update
     camera: Camera,
     camera_controller: CameraController,
-    uniforms: Uniforms,
+    camera_path_player: CameraPathPlayer,
+    input_map: InputMap,
+    gamepads: GamepadBackend,
+    /// Collects every frame's input and timing while recording
+    recording: Option<Recording>,
+    camera_uniform: CameraUniform,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
-    instances: Vec<Instance>,
-    #[allow(dead_code)]
-    instance_buffer: wgpu::Buffer,
+    /// Gameplay side of the scene, extracted into the render data below every update
+    world: World,
+    extractor: Extractor,
+    main_camera: Entity,
+    /// Pivot the security camera sweeps around
+    security_camera_mount: Option<Entity>,
+    /// Instances spawned at runtime, newest last
+    spawned: Vec<Entity>,
+    spawn_root: Option<Entity>,
+    /// Where save_scene writes
+    scene_path: String,
+    scene: SceneGraph,
+    /// One per mesh and material in use, in the order they were first drawn
+    batches: Vec<Batch>,
+    /// This frame's draws of the batches, rebuilt every update
+    draw_list: DrawList,
     depth_texture: texture::Texture,
+    msaa_depth_texture: Option<texture::Texture>,
+    anti_aliasing: AntiAliasing,
+    ssao: Ssao,
+    render_path: RenderPath,
+    deferred: Deferred,
+    lights: Vec<PointLight>,
+    light_buffer: wgpu::Buffer,
+    clustered_lighting: ClusteredLighting,
+    views: Vec<View>,
+    view_compositor: ViewCompositor,
+    /// Layers whose materials sample a view's output, paired with that view's index
+    sampled_layers: Vec<(u32, usize)>,
+    /// Views in the order they're drawn, worked out once the materials sampling them are known
+    view_order: Vec<usize>,
+}
+
+/// The bind groups that change with the camera being drawn
+struct ViewBindings<'a> {
+    camera: &'a wgpu::BindGroup,
+    occlusion: &'a wgpu::BindGroup,
+    layer_mask: u32,
 }
 
 const NUM_INSTANCES_PER_ROW: u32 = 10;
+const NUM_LIGHTS_PER_ROW: u32 = 32;
+const NUM_TRANSPARENT_INSTANCES: u32 = 8;
+/// The mesh instances spawned at runtime are drawn with
+const MESH_PENTAGON: MeshHandle = MeshHandle(0);
+/// The scene's first material, which instances spawned at runtime are drawn with
+const MATERIAL_DEFAULT: MaterialHandle = MaterialHandle(0);
+/// Names of the nodes the app animates or spawns under, if the scene has them
+const SECURITY_CAMERA_MOUNT: &str = "security_camera_mount";
+const SPAWN_ROOT: &str = "spawn_root";
+/// Heading of the security camera mount, facing the grid from its corner
+const SECURITY_CAMERA_YAW: f32 = -std::f32::consts::FRAC_PI_4;
+/// How far either side of its heading the security camera sweeps, and how long a sweep takes
+const SECURITY_CAMERA_SWEEP: f32 = 0.35;
+const SECURITY_CAMERA_SWEEP_PERIOD: f32 = 12.0;
+/// Radians per second the spawned instances turn
+const SPAWNED_SPIN_RATE: f32 = 1.5;
+/// World units visible vertically in the orthographic projection
+const ORTHOGRAPHIC_HEIGHT: f32 = 12.0;
+/// Resolution of the security camera shown on the monitors, independent of the window
+const MONITOR_RESOLUTION: [u32; 2] = [512, 512];
+const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.1,
+    g: 0.2,
+    b: 0.3,
+    a: 1.0,
+};
 const INSTANCE_DISPLACEMENT: glam::Vec3 = glam::const_vec3!([
     NUM_INSTANCES_PER_ROW as f32 * 0.5,
     0.0,
     NUM_INSTANCES_PER_ROW as f32 * 0.5,
 ]);
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 3],
-    tex_coords: [f32; 2],
+/// A material with the view layer it's drawn on, and the description it was made from
+struct MaterialEntry {
+    material: Material,
+    layer: u32,
+    desc: MaterialDesc,
 }
 
-impl Vertex {
-    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::InputStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-            ],
-        }
+/// The instances of one mesh drawn with one material
+struct Batch {
+    mesh: MeshHandle,
+    material: MaterialHandle,
+    instances: InstanceBuffer,
+}
+
+///Fn: Batch Index: Finds the batch drawing the mesh with the material, adding an empty one if there's none yet
+fn batch_index(
+    batches: &mut Vec<Batch>,
+    device: &wgpu::Device,
+    mesh: MeshHandle,
+    material: MaterialHandle,
+) -> usize {
+    if let Some(index) = batches
+        .iter()
+        .position(|batch| batch.mesh == mesh && batch.material == material)
+    {
+        return index;
     }
+    let label = format!("Instance Buffer (mesh {}, material {})", mesh.0, material.0);
+    batches.push(Batch {
+        mesh,
+        material,
+        instances: InstanceBuffer::new(device, &label, iter::empty()),
+    });
+    batches.len() - 1
 }
 
-const VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [-0.0868241, 0.49240386, 0.0],
-        tex_coords: [0.4131759, 0.00759614],
-    }, // A
-    Vertex {
-        position: [-0.49513406, 0.06958647, 0.0],
-        tex_coords: [0.0048659444, 0.43041354],
-    }, // B
-    Vertex {
-        position: [-0.21918549, -0.44939706, 0.0],
-        tex_coords: [0.28081453, 0.949397057],
-    }, // C
-    Vertex {
-        position: [0.35966998, -0.3473291, 0.0],
-        tex_coords: [0.85967, 0.84732911],
-    }, // D
-    Vertex {
-        position: [0.44147372, 0.2347359, 0.0],
-        tex_coords: [0.9414737, 0.2652641],
-    }, // E
-];
-
-const INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4, /* padding */ 0];
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Uniforms {
-    view_proj: Mat4,
+///Fn: Eye Distance: Squared distance from the eye to an instance's mesh centre
+///Params: center is the middle of the mesh's bounds, which sorts better than the instance's origin
+fn eye_distance(eye: Vec3, center: Vec3, instance: &Instance) -> f32 {
+    (instance.model.transform_point3(center) - eye).length_squared()
 }
 
-impl Uniforms {
-    fn new() -> Self {
-        Self {
-            view_proj: Mat4::IDENTITY,
-        }
-    }
+///Fn: Load Texture: Loads an image from the assets directory, loose or from the bundle
+///Params: The images the default scene uses are built in as well, anything else that fails to load is blank
+fn load_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    assets: &Assets,
+    name: &str,
+) -> texture::Texture {
+    let path = format!("{}/{}", scene_file::ASSETS_PATH, name);
+    let bytes = match assets.read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) => match name {
+            "test.png" => Cow::Borrowed(&include_bytes!("../assets/test.png")[..]),
+            "rust.png" => Cow::Borrowed(&include_bytes!("../assets/rust.png")[..]),
+            _ => {
+                log::error!("{:?}", e);
+                return blank_texture(device, queue, name);
+            }
+        },
+    };
+    texture::Texture::from_bytes(device, queue, &bytes, name).unwrap_or_else(|e| {
+        log::error!("Couldn't load {}: {:?}", path, e);
+        blank_texture(device, queue, name)
+    })
+}
 
-    fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix();
+///Fn: Load Mesh: Reads a mesh from the assets, falling back to the built in one when there's none
+///Params: name is one of scene_file::MESH_NAMES
+fn load_mesh(assets: &Assets, name: &str) -> MeshData {
+    let path = mesh_path(name);
+    let built_in = || MeshData::built_in(name).expect("every mesh name has a built in mesh");
+    if !assets.exists(&path) {
+        return built_in();
     }
+    assets
+        .read(&path)
+        .and_then(|bytes| MeshData::from_bytes(&bytes))
+        .unwrap_or_else(|e| {
+            log::error!("Couldn't load {}: {:?}", path, e);
+            built_in()
+        })
 }
 
-struct Instance {
-    position: Vec3,
-    rotation: Quat,
+fn mesh_path(name: &str) -> String {
+    format!("{}/{}.mesh", mesh::MESH_PATH, name)
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct InstanceRaw {
-    model: [[f32; 4]; 4],
+///Fn: Blank Texture: A single white texel, standing in for textures that couldn't be found
+fn blank_texture(device: &wgpu::Device, queue: &wgpu::Queue, label: &str) -> texture::Texture {
+    let white =
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])));
+    texture::Texture::from_image(device, queue, &white, Some(label)).unwrap()
 }
 
-impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (Mat4::from_translation(self.position) * Mat4::from_quat(self.rotation))
-                .to_cols_array_2d(),
-        }
-    }
-}
+///Fn: Default Scene: The scene used when there's no scene file, a grid of instances under a grid of lights
+fn default_scene() -> SceneFile {
+    let pentagon = |name: String, transform: Transform, material: &str| NodeDesc {
+        mesh: Some(MeshDesc {
+            mesh: "pentagon".to_string(),
+            material: material.to_string(),
+            attributes: InstanceAttributes::default(),
+        }),
+        ..NodeDesc::new(&name, transform)
+    };
 
-impl InstanceRaw {
-    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
-            // We need to switch from using a step mode of Vertex to Instance
-            // This means that our shaders will only change to use the next
-            // instance when the shader starts processing a new instance
-            step_mode: wgpu::InputStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials we'll
-                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5 not conflict with them later
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
-                // for each vec4. We don't have to do this in code though.
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
+    // Each group of instances under its own root
+    let mut grid = NodeDesc::new("grid", Transform::from_translation(-INSTANCE_DISPLACEMENT));
+    for z in 0..NUM_INSTANCES_PER_ROW {
+        for x in 0..NUM_INSTANCES_PER_ROW {
+            let local = glam::vec3(x as f32, 0.0, z as f32);
+            let position = local - INSTANCE_DISPLACEMENT;
+
+            let rotation = if position == glam::vec3(0.0, 0.0, 0.0) {
+                // this is needed so an object at (0, 0, 0) won't get scaled to zero
+                // as Quaternions can effect scale if they're not created correctly
+                glam::Quat::from_axis_angle(glam::Vec3::Z, 0.0)
+            } else {
+                glam::Quat::from_axis_angle(position.normalize(), 45.0)
+            };
+
+            grid.children.push(pentagon(
+                format!("grid_{}_{}", x, z),
+                Transform::from_translation_rotation(local, rotation),
+                "test",
+            ));
         }
     }
-}
 
-struct CameraController {
-    speed: f32,
-    is_up_pressed: bool,
-    is_down_pressed: bool,
-    is_forward_pressed: bool,
-    is_backward_pressed: bool,
-    is_left_pressed: bool,
-    is_right_pressed: bool,
-}
+    // A row of cutouts behind the grid
+    let mut cutouts = NodeDesc::new(
+        "cutouts",
+        Transform::from_translation(glam::vec3(0.0, 0.5, -1.5) - INSTANCE_DISPLACEMENT),
+    );
+    for x in 0..NUM_INSTANCES_PER_ROW {
+        cutouts.children.push(pentagon(
+            format!("cutout_{}", x),
+            Transform::from_translation(glam::vec3(x as f32, 0.0, 0.0)),
+            "rust",
+        ));
+    }
 
-impl CameraController {
-    fn new(speed: f32) -> Self {
-        Self {
-            speed,
-            is_up_pressed: false,
-            is_down_pressed: false,
-            is_forward_pressed: false,
-            is_backward_pressed: false,
-            is_left_pressed: false,
-            is_right_pressed: false,
-        }
+    // Staggered panes in front of the grid, so their draw order changes as the camera moves
+    let mut glass = NodeDesc::new(
+        "glass",
+        Transform::from_translation(glam::vec3(0.0, 0.5, INSTANCE_DISPLACEMENT.z + 0.5)),
+    );
+    for x in 0..NUM_TRANSPARENT_INSTANCES {
+        glass.children.push(pentagon(
+            format!("pane_{}", x),
+            Transform::from_translation(glam::vec3(
+                x as f32 * 1.2 - NUM_TRANSPARENT_INSTANCES as f32 * 0.6,
+                0.0,
+                (x % 2) as f32 * 0.6,
+            )),
+            "glass",
+        ));
     }
 
-    fn process_events(&mut self, event: &WindowEvent) -> bool {
-        match event {
-            WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        state,
-                        virtual_keycode: Some(keycode),
-                        ..
-                    },
-                ..
-            } => {
-                let is_pressed = *state == ElementState::Pressed;
-                match keycode {
-                    VirtualKeyCode::Space => {
-                        self.is_up_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::LShift => {
-                        self.is_down_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::W | VirtualKeyCode::Up => {
-                        self.is_forward_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::A | VirtualKeyCode::Left => {
-                        self.is_left_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::S | VirtualKeyCode::Down => {
-                        self.is_backward_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::D | VirtualKeyCode::Right => {
-                        self.is_right_pressed = is_pressed;
-                        true
-                    }
-                    _ => false,
-                }
-            }
-            _ => false,
-        }
+    // Monitors behind the grid showing the security camera, stretched to a screen's aspect
+    let mut monitor_wall = NodeDesc::new(
+        "monitor_wall",
+        Transform::from_translation(glam::vec3(0.0, 2.5, -INSTANCE_DISPLACEMENT.z - 3.0)),
+    );
+    for x in 0..3 {
+        monitor_wall.children.push(pentagon(
+            format!("monitor_{}", x),
+            Transform {
+                translation: glam::vec3(x as f32 * 2.5 - 2.5, 0.0, 0.0),
+                scale: glam::vec3(1.6, 1.2, 1.0),
+                ..Transform::IDENTITY
+            },
+            "monitor",
+        ));
     }
 
-    ///Fn: Update Camera: Updates the transform of the Camera
-    ///Params: Returns &self, and asks for camera of type Camera
-    fn update_camera(&self, camera: &mut Camera) {
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.powf(2.0);
+    // The security camera sits on a mount in a corner that turns it, tilted down at the grid
+    let mut security_camera_mount = NodeDesc::new(
+        SECURITY_CAMERA_MOUNT,
+        Transform::from_translation_rotation(
+            glam::vec3(-8.0, 6.0, 8.0),
+            Quat::from_rotation_y(SECURITY_CAMERA_YAW),
+        ),
+    );
+    security_camera_mount.children.push(NodeDesc {
+        view: Some("security_camera".to_string()),
+        ..NodeDesc::new(
+            "security_camera",
+            Transform::from_translation_rotation(
+                Vec3::ZERO,
+                Quat::from_rotation_x(-(6.0f32).atan2(128.0f32.sqrt())),
+            ),
+        )
+    });
 
-        // Prevents glitching when camera gets too close to the
-        // center of the scene.
-        if self.is_forward_pressed && forward_mag.z > self.speed {
-            camera.eye += forward_norm * self.speed;
-        }
-        if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+    let light_colors = [
+        [1.0, 0.3, 0.2],
+        [0.2, 1.0, 0.4],
+        [0.3, 0.4, 1.0],
+        [1.0, 0.9, 0.5],
+    ];
+    let light_spacing = NUM_INSTANCES_PER_ROW as f32 / NUM_LIGHTS_PER_ROW as f32;
+    let mut lights = NodeDesc::new(
+        "lights",
+        Transform::from_translation(-INSTANCE_DISPLACEMENT),
+    );
+    for z in 0..NUM_LIGHTS_PER_ROW {
+        for x in 0..NUM_LIGHTS_PER_ROW {
+            let color = light_colors[((x + z) % light_colors.len() as u32) as usize];
+            lights.children.push(NodeDesc {
+                light: Some(LightDesc {
+                    color,
+                    radius: 1.0,
+                    intensity: 2.0,
+                }),
+                ..NodeDesc::new(
+                    &format!("light_{}_{}", x, z),
+                    Transform::from_translation(glam::vec3(
+                        (x as f32 + 0.5) * light_spacing,
+                        1.0,
+                        (z as f32 + 0.5) * light_spacing,
+                    )),
+                )
+            });
         }
+    }
 
-        let right = forward_norm.cross(camera.up);
+    // Instances spawned at runtime are dropped in above the grid
+    let spawn_root = NodeDesc::new(
+        SPAWN_ROOT,
+        Transform::from_translation(glam::vec3(0.0, 2.0, 0.0)),
+    );
 
-        let forward = camera.target - camera.eye;
-        let forward_mag = forward.powf(2.0);
+    let materials = vec![
+        MaterialDesc {
+            name: "test".to_string(),
+            texture: TextureRef::File("test.png".to_string()),
+            tint: [1.0; 4],
+            roughness: 0.5,
+            metallic: 0.0,
+            alpha_mode: AlphaMode::Opaque,
+            layer: Layer::Default,
+        },
+        MaterialDesc {
+            name: "rust".to_string(),
+            texture: TextureRef::File("rust.png".to_string()),
+            tint: [1.0; 4],
+            roughness: 0.5,
+            metallic: 0.0,
+            alpha_mode: AlphaMode::Mask(0.5),
+            layer: Layer::Foliage,
+        },
+        MaterialDesc {
+            name: "glass".to_string(),
+            texture: TextureRef::File("test.png".to_string()),
+            tint: [0.6, 0.8, 1.0, 0.4],
+            roughness: 0.1,
+            metallic: 0.0,
+            alpha_mode: AlphaMode::Blend,
+            layer: Layer::Glass,
+        },
+        MaterialDesc {
+            name: "monitor".to_string(),
+            texture: TextureRef::View("security_camera".to_string()),
+            tint: [1.0; 4],
+            roughness: 0.9,
+            metallic: 0.0,
+            alpha_mode: AlphaMode::Opaque,
+            layer: Layer::Screens,
+        },
+    ];
 
-        if self.is_right_pressed {
-            // Rescale the distance between the target and eye so
-            // that it doesn't change. The eye therefore still
-            // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
-        }
-        if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
-        }
-    }
+    let camera = CameraDesc {
+        projection: Projection::Perspective,
+        eye: [0.0, 5.0, 10.0],
+        target: [0.0, 0.0, 0.0],
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+    };
+
+    SceneFile::new(
+        camera,
+        materials,
+        vec![
+            grid,
+            cutouts,
+            glass,
+            monitor_wall,
+            security_camera_mount,
+            lights,
+            spawn_root,
+        ],
+    )
 }
 
 impl State {
     // Creating some of the wgpu types requires async code
-    async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
-
+    ///Fn: New: Sets up the GPU and the scene
+    ///Params: Without a window frames are rendered offscreen at size, for headless replays
+    async fn new(
+        window: Option<&Window>,
+        size: winit::dpi::PhysicalSize<u32>,
+        depth_mode: DepthMode,
+        bindings: InputBindings,
+        scene: SceneFile,
+        assets: &Assets,
+    ) -> Self {
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
-        let surface = unsafe { instance.create_surface(window) };
+        let surface = window.map(|window| unsafe { instance.create_surface(window) });
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
+                compatible_surface: surface.as_ref(),
             })
             .await
             .unwrap();
@@ -311,111 +512,82 @@ impl State {
 
         let swap_chain_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
-            format: adapter.get_swap_chain_preferred_format(&surface).unwrap(),
+            format: match &surface {
+                Some(surface) => adapter.get_swap_chain_preferred_format(surface).unwrap(),
+                None => wgpu::TextureFormat::Bgra8UnormSrgb,
+            },
             width: size.width,
             height: size.height,
             present_mode: wgpu::PresentMode::Fifo,
         };
-        let swap_chain = device.create_swap_chain(&surface, &swap_chain_desc);
-
-        let diffuse_bytes = include_bytes!("../assets/test.png");
-        let diffuse_texture =
-            texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "test.png").unwrap();
+        let output = match surface {
+            Some(surface) => Output::Window {
+                swap_chain: device.create_swap_chain(&surface, &swap_chain_desc),
+                surface,
+            },
+            None => Output::Offscreen(Self::create_offscreen_output(&device, &swap_chain_desc)),
+        };
 
+        let anti_aliasing = AntiAliasing::new(
+            &device,
+            assets,
+            &swap_chain_desc,
+            PostAntiAliasing::Fxaa,
+            antialiasing::MSAA_SAMPLE_COUNT,
+        );
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &swap_chain_desc, "depth_texture");
+            texture::Texture::create_depth_texture(&device, &swap_chain_desc, 1, "depth_texture");
+        let msaa_depth_texture =
+            Self::create_msaa_depth_texture(&device, &swap_chain_desc, anti_aliasing.sample_count);
+        let ssao = Ssao::new(&device, &queue, assets, &swap_chain_desc, &depth_texture);
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler {
-                            comparison: false,
-                            filtering: true,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
-
-        let camera = Camera {
-            eye: (0.0, 5.0, 10.0).into(),
-            target: (0.0, 0.0, 0.0).into(),
-            up: glam::Vec3::Y,
-            aspect: swap_chain_desc.width as f32 / swap_chain_desc.height as f32,
-            fovy: 45.0,
-            znear: 0.1,
-            zfar: 100.0,
-        };
-        let camera_controller = CameraController::new(0.2);
+        let material_bind_group_layout = Material::create_bind_group_layout(&device);
+        let camera = scene.camera.to_camera(
+            depth_mode,
+            swap_chain_desc.width as f32 / swap_chain_desc.height as f32,
+        );
+        let camera_controller = CameraController::new(12.0, &camera);
+        // A slow half circle around the grid, pushing in towards the monitors at the end
+        let camera_path_player = CameraPathPlayer::new(CameraPath::new(vec![
+            Keyframe::from_camera(&camera, 0.0),
+            Keyframe {
+                time: 3.0,
+                eye: (10.0, 4.0, 0.0).into(),
+                target: (0.0, 0.0, 0.0).into(),
+                fovy: 45.0,
+            },
+            Keyframe {
+                time: 6.0,
+                eye: (4.0, 6.0, -10.0).into(),
+                target: (0.0, 0.0, 0.0).into(),
+                fovy: 50.0,
+            },
+            Keyframe {
+                time: 9.0,
+                eye: (0.0, 3.0, -2.0).into(),
+                target: (0.0, 2.5, -INSTANCE_DISPLACEMENT.z - 3.0).into(),
+                fovy: 35.0,
+            },
+        ]));
 
-        let mut uniforms = Uniforms::new();
-        uniforms.update_view_proj(&camera);
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update(
+            &camera,
+            [swap_chain_desc.width as f32, swap_chain_desc.height as f32],
+            0.0,
+        );
 
         let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[uniforms]),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
             usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
         });
 
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let position = glam::vec3(x as f32, 0.0, z as f32) - INSTANCE_DISPLACEMENT;
-
-                    let rotation = if position == glam::vec3(0.0, 0.0, 0.0) {
-                        // this is needed so an object at (0, 0, 0) won't get scaled to zero
-                        // as Quaternions can effect scale if they're not created correctly
-                        glam::Quat::from_axis_angle(glam::Vec3::Z, 0.0)
-                    } else {
-                        glam::Quat::from_axis_angle(position.clone().normalize(), 45.0)
-                    };
-
-                    Instance { position, rotation }
-                })
-            })
-            .collect::<Vec<_>>();
-
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsage::VERTEX,
-        });
-
         let uniform_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStage::VERTEX,
+                    visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -437,131 +609,991 @@ impl State {
 
         let draw_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
             label: Some("Draw Shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../shader/draw.wgsl"))),
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(format!(
+                "{}{}{}{}",
+                assets.shader("camera.wgsl", include_str!("../shader/camera.wgsl")),
+                InstanceRaw::wgsl(assets),
+                ClusteredLighting::wgsl(assets),
+                assets.shader("draw.wgsl", include_str!("../shader/draw.wgsl"))
+            ))),
             flags: wgpu::ShaderFlags::default(),
         });
 
+        let light_buffer = light::create_light_buffer(&device);
+        let clustered_lighting = ClusteredLighting::new(&device, assets, &light_buffer);
+
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &uniform_bind_group_layout],
+                bind_group_layouts: &[
+                    &material_bind_group_layout,
+                    &uniform_bind_group_layout,
+                    &ssao.texture_bind_group_layout,
+                    &clustered_lighting.bind_group_layout,
+                ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &draw_module,
-                entry_point: "main_vs",
-                buffers: &[Vertex::desc(), InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &draw_module,
-                entry_point: "main_fs",
-                targets: &[wgpu::ColorTargetState {
-                    format: swap_chain_desc.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrite::ALL,
-                }],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLAMPING
-                clamp_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
+        let render_pipeline = pipeline::create_render_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &[pipeline::color_target(swap_chain_desc.format)],
+            Some(pipeline::depth_stencil_state(
+                true,
+                depth_mode.compare(wgpu::CompareFunction::LessEqual),
+            )),
+            &[Vertex::desc(), InstanceRaw::desc()],
+            &draw_module,
+            anti_aliasing.sample_count,
+            "Render Pipeline",
+        );
+        let single_sample_render_pipeline = pipeline::create_render_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &[pipeline::color_target(swap_chain_desc.format)],
+            Some(pipeline::depth_stencil_state(
+                true,
+                depth_mode.compare(wgpu::CompareFunction::LessEqual),
+            )),
+            &[Vertex::desc(), InstanceRaw::desc()],
+            &draw_module,
+            1,
+            "Single Sample Render Pipeline",
+        );
+        let transparent_pipeline = Self::create_transparent_pipeline(
+            &device,
+            &render_pipeline_layout,
+            swap_chain_desc.format,
+            &draw_module,
+            anti_aliasing.sample_count,
+            depth_mode,
+        );
+        let single_sample_transparent_pipeline = Self::create_transparent_pipeline(
+            &device,
+            &render_pipeline_layout,
+            swap_chain_desc.format,
+            &draw_module,
+            1,
+            depth_mode,
+        );
+        let depth_pipeline = pipeline::create_depth_only_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &[Vertex::desc(), InstanceRaw::desc()],
+            &draw_module,
+            depth_mode.compare(wgpu::CompareFunction::Less),
+            "Depth Prepass Pipeline",
+        );
+        let oit = WeightedBlendedOit::new(
+            &device,
+            assets,
+            &swap_chain_desc,
+            &render_pipeline_layout,
+            &[Vertex::desc(), InstanceRaw::desc()],
+            &draw_module,
+            anti_aliasing.sample_count,
+            depth_mode,
+        );
+        let deferred = Deferred::new(
+            &device,
+            assets,
+            &swap_chain_desc,
+            &[
+                &material_bind_group_layout,
+                &uniform_bind_group_layout,
+                &ssao.texture_bind_group_layout,
+            ],
+            &ssao.texture_bind_group_layout,
+            &[Vertex::desc(), InstanceRaw::desc()],
+            &depth_texture,
+            depth_mode,
+        );
+
+        // In scene_file::MESH_NAMES order, so MeshHandles index them
+        let meshes = scene_file::MESH_NAMES
+            .iter()
+            .map(|name| Mesh::new(&device, name, &load_mesh(assets, name)))
+            .collect::<Vec<_>>();
+
+        // Top down picture-in-picture in the bottom right corner, without the glass
+        let view_compositor = ViewCompositor::new(&device, assets, &swap_chain_desc);
+        let mut top_view = View::new(
+            &device,
+            assets,
+            &swap_chain_desc,
+            ViewDescriptor {
+                label: "top_view",
+                camera: Camera {
+                    projection: Projection::Orthographic {
+                        height: NUM_INSTANCES_PER_ROW as f32 + 4.0,
+                    },
+                    eye: (0.0, 20.0, 0.0).into(),
+                    target: (0.0, 0.0, 0.0).into(),
+                    up: -glam::Vec3::Z,
+                    ..camera
+                },
+                target: ViewTarget::Viewport(Viewport {
+                    x: 0.7,
+                    y: 0.7,
+                    width: 0.28,
+                    height: 0.28,
+                }),
+                clear_color: Some(wgpu::Color::BLACK),
+                layer_mask: view::ALL_LAYERS & !view::LAYER_GLASS,
             },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+            &uniform_bind_group_layout,
+            &view_compositor,
+            &light_buffer,
+        );
+        top_view.enabled = false;
+
+        // A security camera looking down on the grid from a corner, shown on monitors behind it.
+        // Declared after the top view, which sees the monitors, so render_order has to move it first.
+        let security_view = View::new(
+            &device,
+            assets,
+            &swap_chain_desc,
+            ViewDescriptor {
+                label: "security_camera",
+                // Placed by its entity every update
+                camera: Camera {
+                    projection: Projection::Perspective,
+                    fovy: 60.0,
+                    ..camera
+                },
+                target: ViewTarget::Texture {
+                    width: MONITOR_RESOLUTION[0],
+                    height: MONITOR_RESOLUTION[1],
+                },
+                clear_color: Some(CLEAR_COLOR),
+                layer_mask: view::ALL_LAYERS & !view::LAYER_SCREENS,
             },
-        });
+            &uniform_bind_group_layout,
+            &view_compositor,
+            &light_buffer,
+        );
+        let views = vec![top_view, security_view];
+        let view_labels = views
+            .iter()
+            .map(|view| view.label.as_str())
+            .collect::<Vec<_>>();
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsage::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: wgpu::BufferUsage::INDEX,
-        });
-        let num_indices = INDICES.len() as u32;
+        let mut world = World::new();
+        let main_camera = world.spawn((camera.clone(), MainCamera));
+        scene.spawn(&mut world, &view_labels);
+        let security_camera_mount = ecs::find_named(&world, SECURITY_CAMERA_MOUNT);
+        let spawn_root = ecs::find_named(&world, SPAWN_ROOT);
+
+        let mut sampled_layers = Vec::new();
+        let mut materials = Vec::new();
+        for desc in scene.materials {
+            let loaded;
+            let texture = match &desc.texture {
+                TextureRef::File(name) => {
+                    loaded = load_texture(&device, &queue, assets, name);
+                    &loaded
+                }
+                TextureRef::View(label) => {
+                    match view_labels.iter().position(|view| view == label) {
+                        Some(index) => {
+                            let layer = (desc.layer.mask(), index);
+                            if !sampled_layers.contains(&layer) {
+                                sampled_layers.push(layer);
+                            }
+                            views[index].output_texture()
+                        }
+                        None => {
+                            log::warn!(
+                                "Material {} shows view {}, which doesn't exist",
+                                desc.name,
+                                label
+                            );
+                            loaded = blank_texture(&device, &queue, label);
+                            &loaded
+                        }
+                    }
+                }
+            };
+            materials.push(MaterialEntry {
+                material: Material::new(
+                    &device,
+                    &material_bind_group_layout,
+                    &desc.name,
+                    texture,
+                    desc.uniform(),
+                    desc.alpha_mode,
+                ),
+                layer: desc.layer.mask(),
+                desc,
+            });
+        }
+        let view_order = view::render_order(&views, &sampled_layers);
 
         Self {
-            surface,
+            output,
             device,
             queue,
             swap_chain_desc,
-            swap_chain,
             size,
+            draw_module,
+            render_pipeline_layout,
             render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
-            diffuse_texture,
-            diffuse_bind_group,
+            single_sample_render_pipeline,
+            depth_pipeline,
+            transparent_pipeline,
+            single_sample_transparent_pipeline,
+            transparency: TransparencyMode::Sorted,
+            oit,
+            meshes,
+            materials,
             camera,
             camera_controller,
-            uniforms,
+            camera_path_player,
+            input_map: InputMap::new(bindings),
+            gamepads: GamepadBackend::new(),
+            recording: None,
+            camera_uniform,
             uniform_buffer,
             uniform_bind_group,
-            instances,
-            instance_buffer,
+            world,
+            extractor: Extractor::new(),
+            main_camera,
+            security_camera_mount,
+            spawned: Vec::new(),
+            spawn_root,
+            scene_path: scene_file::SCENE_PATH.to_string(),
+            scene: SceneGraph::new(),
+            batches: Vec::new(),
+            draw_list: DrawList::new(),
             depth_texture,
+            msaa_depth_texture,
+            anti_aliasing,
+            ssao,
+            render_path: RenderPath::Forward,
+            deferred,
+            lights: Vec::new(),
+            light_buffer,
+            clustered_lighting,
+            views,
+            view_compositor,
+            sampled_layers,
+            view_order,
+        }
+    }
+
+    ///Fn: Create Transparent Pipeline: Alpha blended over the opaque result, depth tested but not written
+    fn create_transparent_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        format: wgpu::TextureFormat,
+        module: &wgpu::ShaderModule,
+        sample_count: u32,
+        depth_mode: DepthMode,
+    ) -> wgpu::RenderPipeline {
+        pipeline::create_render_pipeline(
+            device,
+            layout,
+            &[pipeline::blended_color_target(format)],
+            Some(pipeline::depth_stencil_state(
+                false,
+                depth_mode.compare(wgpu::CompareFunction::LessEqual),
+            )),
+            &[Vertex::desc(), InstanceRaw::desc()],
+            module,
+            sample_count,
+            "Transparent Pipeline",
+        )
+    }
+
+    fn create_msaa_depth_texture(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
+    ) -> Option<texture::Texture> {
+        if sample_count > 1 {
+            Some(texture::Texture::create_depth_texture(
+                device,
+                sc_desc,
+                sample_count,
+                "msaa_depth_texture",
+            ))
+        } else {
+            None
         }
     }
 
+    fn create_offscreen_output(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) -> texture::Texture {
+        texture::Texture::create_render_target(
+            device,
+            sc_desc,
+            sc_desc.format,
+            1,
+            "offscreen_output",
+        )
+    }
+
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
         self.swap_chain_desc.width = new_size.width;
         self.swap_chain_desc.height = new_size.height;
-        self.swap_chain = self
-            .device
-            .create_swap_chain(&self.surface, &self.swap_chain_desc);
+        match &mut self.output {
+            Output::Window {
+                surface,
+                swap_chain,
+            } => {
+                *swap_chain = self
+                    .device
+                    .create_swap_chain(surface, &self.swap_chain_desc)
+            }
+            Output::Offscreen(target) => {
+                *target = Self::create_offscreen_output(&self.device, &self.swap_chain_desc)
+            }
+        }
 
-        self.camera.aspect = self.swap_chain_desc.width as f32 / self.swap_chain_desc.height as f32;
+        self.main_camera_mut().aspect =
+            self.swap_chain_desc.width as f32 / self.swap_chain_desc.height as f32;
         self.depth_texture = texture::Texture::create_depth_texture(
             &self.device,
             &self.swap_chain_desc,
+            1,
             "depth_texture",
         );
+        self.msaa_depth_texture = Self::create_msaa_depth_texture(
+            &self.device,
+            &self.swap_chain_desc,
+            self.anti_aliasing.sample_count,
+        );
+        self.ssao
+            .resize(&self.device, &self.swap_chain_desc, &self.depth_texture);
+        self.deferred
+            .resize(&self.device, &self.swap_chain_desc, &self.depth_texture);
+        self.anti_aliasing
+            .resize(&self.device, &self.swap_chain_desc);
+        self.oit.resize(
+            &self.device,
+            &self.swap_chain_desc,
+            self.anti_aliasing.sample_count,
+        );
+        for view in &mut self.views {
+            view.resize(&self.device, &self.swap_chain_desc, &self.view_compositor);
+        }
+    }
+
+    ///Fn: Set Sample Count: Switches multisampling, rebuilding everything tied to the sample count
+    fn set_sample_count(&mut self, sample_count: u32) {
+        self.anti_aliasing.sample_count = sample_count;
+        self.render_pipeline = pipeline::create_render_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            &[pipeline::color_target(self.swap_chain_desc.format)],
+            Some(pipeline::depth_stencil_state(
+                true,
+                self.camera
+                    .depth_mode
+                    .compare(wgpu::CompareFunction::LessEqual),
+            )),
+            &[Vertex::desc(), InstanceRaw::desc()],
+            &self.draw_module,
+            sample_count,
+            "Render Pipeline",
+        );
+        self.transparent_pipeline = Self::create_transparent_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            self.swap_chain_desc.format,
+            &self.draw_module,
+            sample_count,
+            self.camera.depth_mode,
+        );
+        self.resize(self.size);
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.process_events(event)
+        match InputEvent::from_window_event(event) {
+            Some(event) => {
+                self.input_map.apply(&event);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn device_input(&mut self, event: &DeviceEvent) -> bool {
+        match InputEvent::from_device_event(event) {
+            Some(event) => {
+                self.input_map.apply(&event);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn poll_gamepads(&mut self) {
+        self.gamepads.poll(&mut self.input_map);
+    }
+
+    ///Fn: Replay Frame: Puts back a recorded frame's window size and input, in place of live input
+    fn replay_frame(&mut self, frame: &RecordedFrame) {
+        let [width, height] = frame.window_size;
+        if [self.size.width, self.size.height] != frame.window_size {
+            self.resize(winit::dpi::PhysicalSize::new(width, height));
+        }
+        for event in &frame.events {
+            self.input_map.apply(event);
+        }
+    }
+
+    ///Fn: Handle Actions: Applies the render setting and camera path actions pressed this frame
+    fn handle_actions(&mut self) {
+        if self.input_map.just_pressed("toggle_camera_mode") {
+            self.toggle_camera_mode();
+        }
+        if self.input_map.just_pressed("cycle_post_aa") {
+            self.anti_aliasing.mode = self.anti_aliasing.mode.next();
+            log::info!("Post anti-aliasing: {:?}", self.anti_aliasing.mode);
+        }
+        if self.input_map.just_pressed("toggle_msaa") {
+            let sample_count = if self.anti_aliasing.sample_count > 1 {
+                1
+            } else {
+                antialiasing::MSAA_SAMPLE_COUNT
+            };
+            self.set_sample_count(sample_count);
+            log::info!("MSAA samples: {}", sample_count);
+        }
+        if self.input_map.just_pressed("toggle_ssao") {
+            self.ssao.enabled = !self.ssao.enabled;
+            log::info!("SSAO: {}", self.ssao.enabled);
+        }
+        if self.input_map.just_pressed("cycle_render_path") {
+            self.render_path = self.render_path.next();
+            log::info!("Render path: {:?}", self.render_path);
+        }
+        if self.input_map.just_pressed("cycle_transparency") {
+            self.transparency = self.transparency.next();
+            log::info!("Transparency: {:?}", self.transparency);
+        }
+        if self.input_map.just_pressed("cycle_projection") {
+            let mut camera = self.main_camera_mut();
+            // The infinite projection only exists with reverse-Z depth
+            camera.projection = match camera.projection {
+                Projection::Perspective => Projection::Orthographic {
+                    height: ORTHOGRAPHIC_HEIGHT,
+                },
+                Projection::Orthographic { .. } if camera.depth_mode == DepthMode::ReverseZ => {
+                    Projection::InfinitePerspectiveReverseZ
+                }
+                _ => Projection::Perspective,
+            };
+            log::info!("Projection: {:?}", camera.projection);
+        }
+        if self.input_map.just_pressed("toggle_views") {
+            for view in &mut self.views {
+                if let ViewTarget::Texture { .. } = view.target {
+                    continue;
+                }
+                view.enabled = !view.enabled;
+                log::info!("View {}: {}", view.label, view.enabled);
+            }
+        }
+        if self.input_map.just_pressed("record_keyframe") {
+            let keyframe = self.camera_path_player.path.record(&self.camera);
+            log::info!("Recorded camera keyframe at {}s", keyframe.time);
+        }
+        if self.input_map.just_pressed("toggle_camera_path") {
+            if self.camera_path_player.toggle_playback() {
+                log::info!(
+                    "Playing camera path, {}s",
+                    self.camera_path_player.path.duration()
+                );
+            } else {
+                self.resume_camera_controller();
+            }
+        }
+        if self.input_map.just_pressed("clear_camera_path") {
+            self.camera_path_player.path.clear();
+            log::info!("Cleared camera path");
+        }
+        if self.input_map.just_pressed("spawn_instance") {
+            self.spawn_next();
+        }
+        if self.input_map.just_pressed("despawn_instance") {
+            if let Some(node) = self.spawned.pop() {
+                self.despawn(node);
+                log::info!("Despawned instance {}", self.spawned.len() + 1);
+            }
+        }
+        if self.input_map.just_pressed("save_scene") {
+            match self.save_scene() {
+                Ok(()) => log::info!("Saved the scene to {}", self.scene_path),
+                Err(e) => log::error!("{:?}", e),
+            }
+        }
+    }
+
+    ///Fn: Save Scene: Writes the world as it is now, with the main camera and materials, to the scene path
+    fn save_scene(&self) -> anyhow::Result<()> {
+        let view_labels = self
+            .views
+            .iter()
+            .map(|view| view.label.as_str())
+            .collect::<Vec<_>>();
+        let materials = self
+            .materials
+            .iter()
+            .map(|entry| entry.desc.clone())
+            .collect();
+        SceneFile::from_world(
+            &self.world,
+            CameraDesc::from_camera(&self.camera),
+            materials,
+            &view_labels,
+        )
+        .save(&self.scene_path)
+    }
+
+    ///Fn: Toggle Camera Mode: Flips between orbit and fly
+    fn toggle_camera_mode(&mut self) {
+        let mode = match self.camera_controller.mode {
+            CameraMode::Orbit => CameraMode::Fly,
+            CameraMode::Fly => CameraMode::Orbit,
+        };
+        self.camera_controller.set_mode(mode, &self.camera);
+        log::info!("Camera mode: {:?}", mode);
+    }
+
+    ///Fn: Resume Camera Controller: Hands the camera back from path playback, keeping its pose
+    fn resume_camera_controller(&mut self) {
+        self.camera_path_player.playing = false;
+        self.camera_controller
+            .set_mode(self.camera_controller.mode, &self.camera);
+    }
+
+    ///Fn: Main Camera Mut: The main camera entity's Camera, which the next update extracts
+    fn main_camera_mut(&mut self) -> hecs::RefMut<'_, Camera> {
+        self.world
+            .get::<&mut Camera>(self.main_camera)
+            .expect("The main camera entity lost its Camera")
     }
 
-    fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.uniforms.update_view_proj(&self.camera);
+    ///Fn: Update: Advances the simulation by the frame's time steps and uploads the per-frame data
+    ///Params: Reads this frame's input, records it if recording, then clears it for the next frame
+    fn update(&mut self, time: &FrameTime) {
+        self.handle_actions();
+        self.camera_controller.process_input(&self.input_map);
+        let events = self.input_map.end_frame();
+        if let Some(recording) = &mut self.recording {
+            recording.push_frame(*time, [self.size.width, self.size.height], events);
+        }
+        let was_playing = self.camera_path_player.playing;
+        {
+            let mut camera = self
+                .world
+                .get::<&mut Camera>(self.main_camera)
+                .expect("The main camera entity lost its Camera");
+            for _ in 0..time.steps {
+                if self.camera_path_player.playing {
+                    self.camera_path_player
+                        .update_camera(&mut camera, time.step_delta);
+                } else {
+                    self.camera_controller
+                        .update_camera(&mut camera, time.step_delta);
+                }
+            }
+        }
+        self.update_scene(time);
+        if was_playing && !self.camera_path_player.playing {
+            self.resume_camera_controller();
+        }
+        self.camera_uniform.update(
+            &self.camera,
+            [
+                self.swap_chain_desc.width as f32,
+                self.swap_chain_desc.height as f32,
+            ],
+            time.total,
+        );
         self.queue.write_buffer(
             &self.uniform_buffer,
             0,
-            bytemuck::cast_slice(&[self.uniforms]),
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+        self.ssao.update(&self.queue, &self.camera);
+        self.deferred.update(&self.queue, &self.camera);
+        self.clustered_lighting.update(
+            &self.queue,
+            &self.camera,
+            [
+                self.swap_chain_desc.width as f32,
+                self.swap_chain_desc.height as f32,
+            ],
+            self.light_count(),
         );
+        let light_count = self.light_count();
+        for view in self.views.iter_mut().filter(|view| view.enabled) {
+            view.update(&self.queue, &self.swap_chain_desc, light_count, time.total);
+        }
+        self.sort_transparent_instances();
+        self.upload_instances();
+        self.build_draw_list();
+    }
+
+    ///Fn: Light Count: Lights in the light buffer, any past MAX_LIGHTS are left out
+    fn light_count(&self) -> u32 {
+        self.lights.len().min(light::MAX_LIGHTS) as u32
+    }
+
+    ///Fn: Spawn Instance: Adds an entity drawing the mesh with the material, extracted on the next update
+    fn spawn_instance(
+        &mut self,
+        mesh: MeshHandle,
+        material: MaterialHandle,
+        parent: Option<Entity>,
+        local: Transform,
+        attributes: InstanceAttributes,
+    ) -> Entity {
+        let entity = self.world.spawn((local, mesh, material, attributes));
+        if let Some(parent) = parent {
+            self.world.insert_one(entity, Parent(parent)).unwrap();
+        }
+        entity
+    }
+
+    ///Fn: Despawn: Removes an entity and every entity parented below it
+    ///Params: Their instances and lights go with the next update
+    fn despawn(&mut self, entity: Entity) {
+        let mut stack = vec![entity];
+        while let Some(entity) = stack.pop() {
+            let mut children = self.world.query::<(Entity, &Parent)>();
+            stack.extend(
+                children
+                    .iter()
+                    .filter(|(_, parent)| parent.0 == entity)
+                    .map(|(child, _)| child),
+            );
+            drop(children);
+            let _ = self.world.despawn(entity);
+        }
+    }
+
+    fn transform(&self, entity: Entity) -> Option<Transform> {
+        self.world
+            .get::<&Transform>(entity)
+            .ok()
+            .map(|transform| *transform)
+    }
+
+    ///Fn: Set Transform: Moves an entity, whatever is parented below it follows on the next update
+    fn set_transform(&mut self, entity: Entity, local: Transform) {
+        if self.world.insert_one(entity, local).is_err() {
+            log::warn!("Can't move despawned {:?}", entity);
+        }
+    }
+
+    ///Fn: Spawn Next: Drops a new instance in above the grid, spiralling out from the centre
+    fn spawn_next(&mut self) {
+        if self.materials.is_empty() {
+            log::warn!("The scene has no materials to draw spawned instances with");
+            return;
+        }
+        let count = self.spawned.len() as f32;
+        // The golden angle spreads them evenly however many there are
+        let angle = count * 2.399_963;
+        let radius = 0.6 * count.sqrt();
+        let local = Transform::from_translation(glam::vec3(
+            radius * angle.cos(),
+            0.0,
+            radius * angle.sin(),
+        ));
+        // Each one a different hue around the colour wheel
+        let tint = [
+            0.6 + 0.4 * angle.cos(),
+            0.6 + 0.4 * (angle - 2.094).cos(),
+            0.6 + 0.4 * (angle + 2.094).cos(),
+            1.0,
+        ];
+        let attributes = InstanceAttributes {
+            tint,
+            ..Default::default()
+        };
+        let entity = self.spawn_instance(
+            MESH_PENTAGON,
+            MATERIAL_DEFAULT,
+            self.spawn_root,
+            local,
+            attributes,
+        );
+        self.spawned.push(entity);
+        log::info!("Spawned instance {}", self.spawned.len());
+    }
+
+    ///Fn: Update Scene: Animates the entities, then extracts the world into the scene graph, instances, lights and cameras
+    fn update_scene(&mut self, time: &FrameTime) {
+        let sweep = (time.total / SECURITY_CAMERA_SWEEP_PERIOD * std::f32::consts::TAU).sin()
+            * SECURITY_CAMERA_SWEEP;
+        if let Some((entity, mount)) = self
+            .security_camera_mount
+            .and_then(|entity| Some((entity, self.transform(entity)?)))
+        {
+            let mount = Transform {
+                rotation: Quat::from_rotation_y(SECURITY_CAMERA_YAW + sweep),
+                ..mount
+            };
+            self.set_transform(entity, mount);
+        }
+        for index in 0..self.spawned.len() {
+            let entity = self.spawned[index];
+            if let Some(transform) = self.transform(entity) {
+                let spun = Transform {
+                    rotation: Quat::from_rotation_y(time.total * SPAWNED_SPIN_RATE),
+                    ..transform
+                };
+                self.set_transform(entity, spun);
+            }
+        }
+        self.extract();
+    }
+
+    ///Fn: Extract: Copies the world into the render data, touching only what changed since the last update
+    fn extract(&mut self) {
+        let batches = &mut self.batches;
+        let device = &self.device;
+        let removed =
+            self.extractor
+                .extract_scene(&self.world, &mut self.scene, |mesh, material| {
+                    batch_index(batches, device, mesh, material)
+                });
+        for (node, attachments) in removed {
+            for attachment in attachments {
+                if let Attachment::Mesh(batch) = attachment {
+                    self.batches[batch].instances.remove(node);
+                }
+            }
+        }
+        let mut lights_changed = self.extractor.extract_lights(&self.world, &mut self.lights);
+
+        for node in self.scene.update_world_matrices() {
+            let world = self.scene.world_matrix(node);
+            for &attachment in self.scene.attachments(node) {
+                match attachment {
+                    Attachment::Mesh(batch) => self.batches[batch].instances.set_model(node, world),
+                    Attachment::Light(index) => {
+                        self.lights[index].position = world.w_axis.truncate().into();
+                        lights_changed = true;
+                    }
+                    Attachment::Camera(index) => self.views[index].camera.place(world),
+                }
+            }
+        }
+        for (node, attributes) in self.extractor.extract_instance_attributes(&self.world) {
+            for &attachment in self.scene.attachments(node) {
+                if let Attachment::Mesh(batch) = attachment {
+                    self.batches[batch]
+                        .instances
+                        .set_attributes(node, attributes);
+                }
+            }
+        }
+
+        if lights_changed {
+            if self.lights.len() > light::MAX_LIGHTS {
+                log::warn!(
+                    "{} lights, only the first {} are drawn",
+                    self.lights.len(),
+                    light::MAX_LIGHTS
+                );
+            }
+            let light_count = self.light_count() as usize;
+            self.queue.write_buffer(
+                &self.light_buffer,
+                0,
+                bytemuck::cast_slice(&self.lights[..light_count]),
+            );
+        }
+        if let Some(camera) = ecs::extract_main_camera(&self.world) {
+            self.camera = camera;
+        }
+    }
+
+    fn is_blended(&self, batch: &Batch) -> bool {
+        self.materials[batch.material.0].material.alpha_mode == AlphaMode::Blend
+    }
+
+    ///Fn: Sort Transparent Instances: Orders each blended batch's instances back to front from the eye
+    ///Params: build_draw_list then interleaves the batches
+    fn sort_transparent_instances(&mut self) {
+        let eye = self.camera.eye;
+        for index in 0..self.batches.len() {
+            if !self.is_blended(&self.batches[index]) {
+                continue;
+            }
+            let center = self.meshes[self.batches[index].mesh.0].bounds.center();
+            self.batches[index].instances.sort_by(|a, b| {
+                eye_distance(eye, center, b)
+                    .partial_cmp(&eye_distance(eye, center, a))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+    }
+
+    ///Fn: Upload Instances: Writes every batch's changed instances, growing their buffers as needed
+    fn upload_instances(&mut self) {
+        for batch in &mut self.batches {
+            batch.instances.upload(&self.device, &self.queue);
+        }
+    }
+
+    ///Fn: Build Draw List: Lists a draw for every submesh of every batch with instances
+    ///Params: Opaque batches are drawn whole and sorted by material and mesh. Blended batches are
+    ///Params: ordered back to front for the main camera here, and for each enabled view in its own list.
+    fn build_draw_list(&mut self) {
+        self.draw_list.clear();
+        for (index, batch) in self.batches.iter().enumerate() {
+            if batch.instances.is_empty() || self.is_blended(batch) {
+                continue;
+            }
+            for submesh in 0..self.meshes[batch.mesh.0].submeshes.len() {
+                self.draw_list.push_opaque(DrawItem {
+                    material: batch.material,
+                    mesh: batch.mesh,
+                    submesh,
+                    batch: index,
+                    instances: 0..batch.instances.len() as u32,
+                });
+            }
+        }
+        self.draw_list.sort();
+        for item in self.blended_draws(self.camera.eye) {
+            self.draw_list.push_blended(item);
+        }
+        let view_blended = self
+            .views
+            .iter()
+            .map(|view| {
+                if view.enabled {
+                    self.blended_draws(view.camera.eye)
+                } else {
+                    Vec::new()
+                }
+            })
+            .collect::<Vec<_>>();
+        for (view, blended) in self.views.iter_mut().zip(view_blended) {
+            view.blended = blended;
+        }
+    }
+
+    ///Fn: Blended Draws: The blended batches' draws back to front from eye
+    ///Params: A batch is split into runs wherever another batch's instances fall between, or where its
+    ///Params: instances, sorted for the main camera, are out of order from this eye
+    fn blended_draws(&self, eye: Vec3) -> Vec<DrawItem> {
+        let blended = self
+            .batches
+            .iter()
+            .enumerate()
+            .filter(|(_, batch)| !batch.instances.is_empty() && self.is_blended(batch))
+            .map(|(index, batch)| {
+                let center = self.meshes[batch.mesh.0].bounds.center();
+                let distances = batch
+                    .instances
+                    .instances()
+                    .iter()
+                    .map(|instance| eye_distance(eye, center, instance))
+                    .collect();
+                (index, distances)
+            })
+            .collect::<Vec<_>>();
+        let mut items = Vec::new();
+        for (index, instances) in draw_list::back_to_front(&blended) {
+            let batch = &self.batches[index];
+            for submesh in 0..self.meshes[batch.mesh.0].submeshes.len() {
+                items.push(DrawItem {
+                    material: batch.material,
+                    mesh: batch.mesh,
+                    submesh,
+                    batch: index,
+                    instances: instances.clone(),
+                });
+            }
+        }
+        items
+    }
+
+    ///Fn: Main View Bindings: The window camera with its screen-space occlusion, drawing every layer
+    fn main_view_bindings(&self) -> ViewBindings<'_> {
+        ViewBindings {
+            camera: &self.uniform_bind_group,
+            occlusion: &self.ssao.output_bind_group,
+            layer_mask: view::ALL_LAYERS,
+        }
+    }
+
+    ///Fn: Draw Items: Binds the scene bind groups and buffers and draws the items in order
+    ///Params: Works with any pipeline laid out as material, camera, SSAO. Only rebinds the material and
+    ///Params: mesh when they change, and skips items whose material's layer the view masks out
+    fn draw_items<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        view: &ViewBindings<'a>,
+        items: &[DrawItem],
+    ) {
+        render_pass.set_bind_group(1, view.camera, &[]);
+        render_pass.set_bind_group(2, view.occlusion, &[]);
+        let mut bound_material = None;
+        let mut bound_mesh = None;
+        for item in items {
+            let material = &self.materials[item.material.0];
+            if view.layer_mask & material.layer == 0 {
+                continue;
+            }
+            if bound_material != Some(item.material) {
+                render_pass.set_bind_group(0, &material.material.bind_group, &[]);
+                bound_material = Some(item.material);
+            }
+            let mesh = &self.meshes[item.mesh.0];
+            if bound_mesh != Some(item.mesh) {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+                bound_mesh = Some(item.mesh);
+            }
+            let instances = &self.batches[item.batch].instances;
+            render_pass.set_vertex_buffer(1, instances.buffer().slice(..));
+            let submesh = &mesh.submeshes[item.submesh];
+            render_pass.draw_indexed(
+                submesh.indices.clone(),
+                submesh.base_vertex,
+                item.instances.clone(),
+            );
+        }
+    }
+
+    ///Fn: Draw Scene: Draws everything that isn't blended
+    fn draw_scene<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, view: &ViewBindings<'a>) {
+        self.draw_items(render_pass, view, self.draw_list.opaque());
+    }
+
+    ///Fn: Draw Transparent: Draws the blended instances, already ordered back to front in update
+    ///Params: Needs a transparent pipeline and the cluster bind group set on the pass
+    fn draw_transparent<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        view: &ViewBindings<'a>,
+    ) {
+        self.draw_items(render_pass, view, self.draw_list.blended());
     }
 
     fn render(&mut self) -> Result<(), wgpu::SwapChainError> {
-        let frame = self.swap_chain.get_current_frame()?.output;
+        let frame = match &self.output {
+            Output::Window { swap_chain, .. } => Some(swap_chain.get_current_frame()?.output),
+            Output::Offscreen(_) => None,
+        };
+        let frame_view = match (&frame, &self.output) {
+            (Some(frame), _) => &frame.view,
+            (None, Output::Offscreen(target)) => &target.view,
+            (None, Output::Window { .. }) => unreachable!(),
+        };
 
         let mut encoder = self
             .device
@@ -569,26 +1601,66 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        // Views go first, so any texture they render is ready before the main view samples it
+        self.render_views(&mut encoder);
+        match self.render_path {
+            RenderPath::Forward => self.render_forward(&mut encoder, frame_view),
+            RenderPath::Deferred => self.render_deferred(&mut encoder, frame_view),
+        }
+        self.view_compositor.composite(
+            &mut encoder,
+            self.anti_aliasing.scene_view(frame_view),
+            &self.swap_chain_desc,
+            &self.views,
+        );
+
+        self.anti_aliasing.render(&mut encoder, frame_view);
+
+        self.queue.submit(iter::once(encoder.finish()));
+
+        Ok(())
+    }
+
+    fn render_forward(&self, encoder: &mut wgpu::CommandEncoder, frame_view: &wgpu::TextureView) {
+        let far_depth = self.camera.depth_mode.far_depth();
+        // SSAO needs the scene depth before the colour pass samples the occlusion
+        if self.ssao.enabled {
+            let mut depth_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Prepass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(far_depth),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            depth_pass.set_pipeline(&self.depth_pipeline);
+            depth_pass.set_bind_group(3, &self.clustered_lighting.bind_group, &[]);
+            self.draw_scene(&mut depth_pass, &self.main_view_bindings());
+        }
+        self.ssao.render(encoder);
+        self.clustered_lighting.cull_lights(encoder);
+
+        // Without multisampling the prepass depth can be reused as is
+        let (depth_view, depth_load) = match &self.msaa_depth_texture {
+            Some(msaa_depth_texture) => (&msaa_depth_texture.view, wgpu::LoadOp::Clear(far_depth)),
+            None if self.ssao.enabled => (&self.depth_texture.view, wgpu::LoadOp::Load),
+            None => (&self.depth_texture.view, wgpu::LoadOp::Clear(far_depth)),
+        };
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
-                color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view: &frame.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
+                color_attachments: &[self
+                    .anti_aliasing
+                    .scene_attachment(frame_view, wgpu::LoadOp::Clear(CLEAR_COLOR))],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
+                    view: depth_view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: depth_load,
                         store: true,
                     }),
                     stencil_ops: None,
@@ -596,60 +1668,394 @@ impl State {
             });
 
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            // UPDATED!
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
+            render_pass.set_bind_group(3, &self.clustered_lighting.bind_group, &[]);
+            self.draw_scene(&mut render_pass, &self.main_view_bindings());
+
+            if self.transparency == TransparencyMode::Sorted {
+                render_pass.set_pipeline(&self.transparent_pipeline);
+                self.draw_transparent(&mut render_pass, &self.main_view_bindings());
+            }
         }
 
-        self.queue.submit(iter::once(encoder.finish()));
+        if self.transparency == TransparencyMode::WeightedBlended {
+            self.render_oit(
+                encoder,
+                frame_view,
+                depth_view,
+                self.msaa_depth_texture.is_some(),
+            );
+        }
+    }
 
-        Ok(())
+    ///Fn: Render OIT: Accumulates the blended instances then composites them over the scene view
+    ///Params: depth_view holds the opaque depth, multisampled when it's the MSAA depth texture
+    fn render_oit(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        multisampled: bool,
+    ) {
+        {
+            let mut accumulation_pass =
+                self.oit
+                    .begin_accumulation_pass(encoder, depth_view, multisampled);
+            accumulation_pass.set_bind_group(3, &self.clustered_lighting.bind_group, &[]);
+            self.draw_transparent(&mut accumulation_pass, &self.main_view_bindings());
+        }
+        self.oit
+            .composite(encoder, self.anti_aliasing.scene_view(frame_view));
+    }
+
+    ///Fn: Render Deferred: G-buffer, SSAO, lighting, then forward shaded transparents into the single sampled scene view
+    ///Params: Multisampling doesn't apply here, use the post-process anti-aliasing instead
+    fn render_deferred(&self, encoder: &mut wgpu::CommandEncoder, frame_view: &wgpu::TextureView) {
+        {
+            let mut gbuffer_pass = self
+                .deferred
+                .begin_gbuffer_pass(encoder, &self.depth_texture.view);
+            self.draw_scene(&mut gbuffer_pass, &self.main_view_bindings());
+        }
+        self.ssao.render(encoder);
+        self.deferred.lighting_pass(
+            encoder,
+            self.anti_aliasing.scene_view(frame_view),
+            CLEAR_COLOR,
+            &self.ssao.output_bind_group,
+            &self.light_buffer,
+            self.light_count(),
+        );
+
+        // The G-buffer can't hold blended surfaces, so they're lit by the clustered forward shader
+        self.clustered_lighting.cull_lights(encoder);
+        if self.transparency == TransparencyMode::WeightedBlended {
+            self.render_oit(encoder, frame_view, &self.depth_texture.view, false);
+            return;
+        }
+        let mut transparent_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transparent Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: self.anti_aliasing.scene_view(frame_view),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        transparent_pass.set_pipeline(&self.single_sample_transparent_pipeline);
+        transparent_pass.set_bind_group(3, &self.clustered_lighting.bind_group, &[]);
+        self.draw_transparent(&mut transparent_pass, &self.main_view_bindings());
+    }
+
+    ///Fn: Render Views: Draws the extra views into their own targets, ordered by what they sample
+    ///Params: Views are forward shaded without occlusion or post-processing of their own
+    fn render_views(&self, encoder: &mut wgpu::CommandEncoder) {
+        // Disabled views keep last frame's image, which is safe to sample
+        let mut rendered = self
+            .views
+            .iter()
+            .map(|view| !view.enabled)
+            .collect::<Vec<_>>();
+        for &index in &self.view_order {
+            let view = &self.views[index];
+            if !view.enabled {
+                continue;
+            }
+            view.clustered_lighting.cull_lights(encoder);
+            let bindings = ViewBindings {
+                camera: &view.uniform_bind_group,
+                occlusion: &self.ssao.unoccluded_bind_group,
+                layer_mask: view.layer_mask
+                    & !view::unready_layers(&self.sampled_layers, &rendered),
+            };
+            let mut render_pass = view.begin_render_pass(encoder);
+            render_pass.set_pipeline(&self.single_sample_render_pipeline);
+            render_pass.set_bind_group(3, &view.clustered_lighting.bind_group, &[]);
+            self.draw_scene(&mut render_pass, &bindings);
+            // Blended from this view's eye, the main draw list is ordered for the main camera's
+            render_pass.set_pipeline(&self.single_sample_transparent_pipeline);
+            self.draw_items(&mut render_pass, &bindings, &view.blended);
+            drop(render_pass);
+            rendered[index] = true;
+        }
     }
 }
 
+///Fn: Arg Value: The argument following name on the command line, as in `--replay input.ron`
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next()?;
+    args.next()
+}
+
+///Fn: Load Scene: The scene at path, or the default scene if there's no file there or it won't load
+fn load_scene_or_default(assets: &Assets, path: &str) -> SceneFile {
+    if !assets.exists(path) {
+        log::info!("No scene at {}, using the default scene", path);
+        return default_scene();
+    }
+    SceneFile::load(assets, path).unwrap_or_else(|e| {
+        log::warn!("Using the default scene: {:?}", e);
+        default_scene()
+    })
+}
+
+///Fn: Run Headless: Plays a recording back without a window, rendering every frame offscreen
+fn run_headless(mut replay: Replay, scene: SceneFile, assets: &Assets) {
+    let recording = replay.recording();
+    let [width, height] = recording
+        .frames
+        .first()
+        .map_or(WINDOW_SIZE, |frame| frame.window_size);
+    let mut state = block_on(State::new(
+        None,
+        winit::dpi::PhysicalSize::new(width, height),
+        recording.depth_mode,
+        recording.bindings.clone(),
+        scene,
+        assets,
+    ));
+    let mut frame_count = 0;
+    while let Some(frame) = replay.next_frame() {
+        state.replay_frame(frame);
+        state.update(&frame.time);
+        if let Err(e) = state.render() {
+            log::error!("Frame {} failed to render: {:?}", frame_count, e);
+        }
+        // Keep the GPU in step rather than queueing up the whole replay
+        state.device.poll(wgpu::Maintain::Wait);
+        frame_count += 1;
+    }
+    log::info!(
+        "Replayed {} frames, the camera ended at {} looking at {}",
+        frame_count,
+        state.camera.eye,
+        state.camera.target
+    );
+}
+
+///Fn: Build Bundle: The bundle subcommand, `bundle [--output <file>] [directory...]`
+///Params: Packs the default directories when none are given
+fn build_bundle(args: &[String]) -> anyhow::Result<()> {
+    let mut output = bundle::BUNDLE_PATH.to_string();
+    let mut directories = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.as_slice().first()) {
+            ("--output", Some(path)) => {
+                output = path.clone();
+                args.next();
+            }
+            _ => directories.push(arg.clone()),
+        }
+    }
+    if directories.is_empty() {
+        directories = bundle::BUNDLE_DIRECTORIES
+            .iter()
+            .map(|directory| directory.to_string())
+            .collect();
+    }
+    // The built in meshes go in too, so a bundle holds every mesh a scene can name
+    let meshes = scene_file::MESH_NAMES
+        .iter()
+        .filter_map(|name| Some((mesh_path(name), MeshData::built_in(name)?.to_bytes())))
+        .collect::<Vec<_>>();
+    bundle::build(&directories, &meshes, &output)?;
+    let bundle = Bundle::open(&output)?;
+    let mut entries = bundle.entries().collect::<Vec<_>>();
+    entries.sort_by_key(|(name, _)| *name);
+    for (name, entry) in entries {
+        log::info!("{} ({:?}, {} bytes)", name, entry.kind, entry.size());
+    }
+    Ok(())
+}
+
 fn main() {
     env_logger::init();
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("bundle") {
+        if let Err(e) = build_bundle(&args[2..]) {
+            log::error!("{:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Replays bring their own settings and bindings, so they play back as they were recorded
+    let mut replay = match arg_value("--replay").map(Recording::load) {
+        Some(Ok(recording)) => Some(Replay::new(recording)),
+        Some(Err(e)) => {
+            log::error!("{:?}", e);
+            return;
+        }
+        None => None,
+    };
+    // Shipping builds read everything from a bundle rather than loose files
+    let assets = match arg_value("--bundle").map(Bundle::open) {
+        Some(Ok(bundle)) => Assets::Bundle(bundle),
+        Some(Err(e)) => {
+            log::error!("{:?}", e);
+            return;
+        }
+        None => Assets::Loose,
+    };
+    // A replay plays the scene it was recorded in. A scene asked for by name has to load,
+    // the default one falls back to the built in scene.
+    let (scene_path, scene) = match (&replay, arg_value("--scene")) {
+        (Some(replay), path) => {
+            if path.is_some() {
+                log::warn!("--scene is ignored while replaying, the recording has its own scene");
+            }
+            (
+                scene_file::SCENE_PATH.to_string(),
+                replay.recording().scene.clone(),
+            )
+        }
+        (None, Some(path)) => match SceneFile::load(&assets, &path) {
+            Ok(scene) => (path, scene),
+            Err(e) => {
+                log::error!("{:?}", e);
+                return;
+            }
+        },
+        (None, None) => (
+            scene_file::SCENE_PATH.to_string(),
+            load_scene_or_default(&assets, scene_file::SCENE_PATH),
+        ),
+    };
+    if std::env::args().any(|arg| arg == "--headless") {
+        match replay {
+            Some(replay) => run_headless(replay, scene, &assets),
+            None => log::error!("--headless needs a recording to play with --replay <file>"),
+        }
+        return;
+    }
+    let record_path = arg_value("--record");
 
     // Since main can't be async, we're going to need to block
-    let mut state = block_on(State::new(&window));
+    // Reverse-Z is picked at startup, as every depth tested pipeline is built for one convention
+    let (depth_mode, bindings) = match &replay {
+        Some(replay) => (
+            replay.recording().depth_mode,
+            replay.recording().bindings.clone(),
+        ),
+        None if std::env::args().any(|arg| arg == "--reverse-z") => (
+            DepthMode::ReverseZ,
+            InputBindings::load_or_default(input::INPUT_CONFIG_PATH),
+        ),
+        None => (
+            DepthMode::Standard,
+            InputBindings::load_or_default(input::INPUT_CONFIG_PATH),
+        ),
+    };
+    let [width, height] = replay
+        .as_ref()
+        .and_then(|replay| replay.recording().frames.first())
+        .map_or(WINDOW_SIZE, |frame| frame.window_size);
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_inner_size(winit::dpi::PhysicalSize::new(width, height))
+        .build(&event_loop)
+        .unwrap();
+    let recording = record_path
+        .as_ref()
+        .map(|_| Recording::new(depth_mode, bindings.clone(), scene.clone()));
+    let mut state = block_on(State::new(
+        Some(&window),
+        window.inner_size(),
+        depth_mode,
+        bindings,
+        scene,
+        &assets,
+    ));
+    state.scene_path = scene_path;
+    state.recording = recording;
+    let mut timer = FrameTimer::new();
+    let mut last_title_update = 0.0;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
+            // Live input is ignored while a replay is driving
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == window.id() => {
-                if !state.input(event) {
-                    // UPDATED!
-                    match event {
-                        WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                        WindowEvent::KeyboardInput { input, .. } => match input {
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            } => *control_flow = ControlFlow::Exit,
-                            _ => {}
-                        },
-                        WindowEvent::Resized(physical_size) => {
-                            state.resize(*physical_size);
-                        }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            // new_inner_size is &&mut so w have to dereference it twice
-                            state.resize(**new_inner_size);
-                        }
-                        _ => {}
+            } if window_id == window.id() && (replay.is_some() || !state.input(event)) => {
+                // UPDATED!
+                match event {
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(*physical_size);
+                    }
+                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                        // new_inner_size is &&mut so w have to dereference it twice
+                        state.resize(**new_inner_size);
                     }
+                    _ => {}
                 }
             }
+            Event::DeviceEvent { ref event, .. } if replay.is_none() => {
+                state.device_input(event);
+            }
             Event::RedrawRequested(_) => {
-                state.update();
+                let time = match &mut replay {
+                    Some(replay) => match replay.next_frame() {
+                        Some(frame) => {
+                            let [width, height] = frame.window_size;
+                            if window.inner_size() != winit::dpi::PhysicalSize::new(width, height) {
+                                window.set_inner_size(winit::dpi::PhysicalSize::new(width, height));
+                            }
+                            state.replay_frame(frame);
+                            frame.time
+                        }
+                        None => {
+                            log::info!("Replay finished");
+                            *control_flow = ControlFlow::Exit;
+                            return;
+                        }
+                    },
+                    None => {
+                        state.poll_gamepads();
+                        timer.tick()
+                    }
+                };
+
+                // Actions that need the window or the timer, State::update handles the rest
+                if state.input_map.just_pressed("exit") {
+                    *control_flow = ControlFlow::Exit;
+                }
+                if state.input_map.just_pressed("toggle_fixed_timestep") {
+                    timer.fixed_timestep = match timer.fixed_timestep {
+                        Some(_) => None,
+                        None => Some(FIXED_TIMESTEP),
+                    };
+                    log::info!("Fixed timestep: {:?}", timer.fixed_timestep);
+                }
+                if time.total - last_title_update >= 1.0 {
+                    window.set_title(&format!("{} - {:.0} fps", APP_NAME, time.fps));
+                    last_title_update = time.total;
+                }
+
+                let camera_mode = state.camera_controller.mode;
+                state.update(&time);
+                if state.camera_controller.mode != camera_mode {
+                    // Flying looks around with the mouse, so keep the cursor inside the window
+                    let flying = state.camera_controller.mode == CameraMode::Fly;
+                    if let Err(e) = window.set_cursor_grab(flying) {
+                        log::warn!("Couldn't grab the cursor: {:?}", e);
+                    }
+                    window.set_cursor_visible(!flying);
+                }
                 match state.render() {
                     Ok(_) => {}
                     // Recreate the swap_chain if lost
@@ -665,6 +2071,14 @@ fn main() {
                 // request it.
                 window.request_redraw();
             }
+            Event::LoopDestroyed => {
+                if let (Some(path), Some(recording)) = (&record_path, &state.recording) {
+                    match recording.save(path) {
+                        Ok(()) => log::info!("Saved {} frames to {}", recording.frames.len(), path),
+                        Err(e) => log::error!("{:?}", e),
+                    }
+                }
+            }
             _ => {}
         }
     });
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::texture;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub tint: [f32; 4],
+    pub roughness: f32,
+    pub metallic: f32,
+    /// Fragments with a lower alpha are discarded, zero keeps everything
+    pub alpha_cutoff: f32,
+    pub _padding: f32,
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self {
+            tint: [1.0; 4],
+            roughness: 0.5,
+            metallic: 0.0,
+            alpha_cutoff: 0.0,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// How a material's alpha is treated, which also decides the pass it is drawn in
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AlphaMode {
+    /// Alpha is ignored
+    Opaque,
+    /// Alpha tested against the cutoff, drawn with the opaque geometry (foliage, fences)
+    Mask(f32),
+    /// Alpha blended after the opaque geometry, sorted back to front
+    Blend,
+}
+
+/// Surface description shared by every render path, bound at group 0:
+/// the diffuse texture, its sampler and the MaterialUniform parameters.
+/// The bind group keeps the texture and uniform buffer alive, nothing else needs them.
+pub struct Material {
+    pub alpha_mode: AlphaMode,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        diffuse_texture: &texture::Texture,
+        mut uniform: MaterialUniform,
+        alpha_mode: AlphaMode,
+    ) -> Self {
+        uniform.alpha_cutoff = match alpha_mode {
+            AlphaMode::Mask(cutoff) => cutoff,
+            AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(&format!("{}_material_bind_group", name)),
+        });
+
+        Self {
+            alpha_mode,
+            bind_group,
+        }
+    }
+
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("material_bind_group_layout"),
+        })
+    }
+}
@@ -0,0 +1,527 @@
+use std::{mem, ops::Range};
+
+use anyhow::{bail, ensure, Context, Result};
+use glam::Vec3;
+use wgpu::util::DeviceExt;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+}
+
+impl Vertex {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+/// Where bundles keep mesh data, each mesh under its name with a .mesh extension
+pub const MESH_PATH: &str = "meshes";
+/// Starts every serialized mesh
+const MESH_MAGIC: [u8; 4] = *b"MESH";
+/// Bumped whenever the serialized layout changes
+const MESH_VERSION: u32 = 1;
+
+/// The start of a serialized mesh, followed by its submeshes, vertices and indices
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MeshHeader {
+    magic: [u8; 4],
+    version: u32,
+    vertex_count: u32,
+    index_count: u32,
+    /// 2 for u16 indices, 4 for u32
+    index_size: u32,
+    submesh_count: u32,
+}
+
+/// A Submesh as it's serialized
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SubmeshRaw {
+    start: u32,
+    end: u32,
+    base_vertex: i32,
+}
+
+const PENTAGON_VERTICES: &[Vertex] = &[
+    Vertex {
+        position: [-0.0868241, 0.49240386, 0.0],
+        tex_coords: [0.4131759, 0.00759614],
+        normal: [0.0, 0.0, 1.0],
+    }, // A
+    Vertex {
+        position: [-0.49513406, 0.06958647, 0.0],
+        tex_coords: [0.0048659444, 0.43041354],
+        normal: [0.0, 0.0, 1.0],
+    }, // B
+    Vertex {
+        position: [-0.21918549, -0.44939706, 0.0],
+        tex_coords: [0.28081453, 0.949_397],
+        normal: [0.0, 0.0, 1.0],
+    }, // C
+    Vertex {
+        position: [0.35966998, -0.3473291, 0.0],
+        tex_coords: [0.85967, 0.84732911],
+        normal: [0.0, 0.0, 1.0],
+    }, // D
+    Vertex {
+        position: [0.44147372, 0.2347359, 0.0],
+        tex_coords: [0.9414737, 0.2652641],
+        normal: [0.0, 0.0, 1.0],
+    }, // E
+];
+
+// Buffers are padded to 4 bytes when they're created, so an odd count of u16s is fine
+const PENTAGON_INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
+
+/// A mesh's indices, u16 when it has few enough vertices, halving the index buffer
+#[derive(Clone, Debug, PartialEq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    /// For meshes with more vertices than a u16 can index
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    ///Fn: New: The narrowest indices that can address every vertex
+    ///Params: vertex_count is the number of vertices the indices point into
+    pub fn new(indices: Vec<u32>, vertex_count: usize) -> Self {
+        if vertex_count <= u16::MAX as usize + 1 {
+            Indices::U16(indices.into_iter().map(|index| index as u16).collect())
+        } else {
+            Indices::U32(indices)
+        }
+    }
+
+    pub fn format(&self) -> wgpu::IndexFormat {
+        match self {
+            Indices::U16(_) => wgpu::IndexFormat::Uint16,
+            Indices::U32(_) => wgpu::IndexFormat::Uint32,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Indices::U16(indices) => bytemuck::cast_slice(indices),
+            Indices::U32(indices) => bytemuck::cast_slice(indices),
+        }
+    }
+}
+
+/// A run of a mesh's indices drawn in one call, so parts of a mesh can be drawn on their own
+#[derive(Clone, Debug, PartialEq)]
+pub struct Submesh {
+    pub indices: Range<u32>,
+    /// Added to every index in the range, for parts whose indices start from their own first vertex
+    pub base_vertex: i32,
+}
+
+/// Box around a mesh's vertices, in its own space
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Bounds {
+    ///Fn: From Points: The smallest box holding every point, an empty box at the origin without any
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut points = points.into_iter();
+        let first = match points.next() {
+            Some(point) => point,
+            None => {
+                return Self {
+                    min: Vec3::ZERO,
+                    max: Vec3::ZERO,
+                }
+            }
+        };
+        points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |bounds, point| Self {
+                min: bounds.min.min(point),
+                max: bounds.max.max(point),
+            },
+        )
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+}
+
+/// A mesh's vertices, indices and submeshes on the CPU, before they're uploaded
+#[derive(Clone, Debug)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Indices,
+    pub submeshes: Vec<Submesh>,
+}
+
+impl MeshData {
+    ///Fn: New: Picks u16 or u32 indices to fit the vertex count
+    ///Params: Without any submeshes the whole index buffer is drawn as one
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>, mut submeshes: Vec<Submesh>) -> Self {
+        if submeshes.is_empty() {
+            submeshes.push(Submesh {
+                indices: 0..indices.len() as u32,
+                base_vertex: 0,
+            });
+        }
+        Self {
+            indices: Indices::new(indices, vertices.len()),
+            vertices,
+            submeshes,
+        }
+    }
+
+    ///Fn: Built In: The meshes the engine can make without any data, by their scene_file::MESH_NAMES name
+    pub fn built_in(name: &str) -> Option<Self> {
+        match name {
+            "pentagon" => Some(Self::pentagon()),
+            "cube" => Some(Self::cube()),
+            _ => None,
+        }
+    }
+
+    ///Fn: To Bytes: Serializes the mesh for a bundle, read back with from_bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = MeshHeader {
+            magic: MESH_MAGIC,
+            version: MESH_VERSION,
+            vertex_count: self.vertices.len() as u32,
+            index_count: self.indices.len() as u32,
+            index_size: match self.indices {
+                Indices::U16(_) => 2,
+                Indices::U32(_) => 4,
+            },
+            submesh_count: self.submeshes.len() as u32,
+        };
+        let submeshes = self
+            .submeshes
+            .iter()
+            .map(|submesh| SubmeshRaw {
+                start: submesh.indices.start,
+                end: submesh.indices.end,
+                base_vertex: submesh.base_vertex,
+            })
+            .collect::<Vec<_>>();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(bytemuck::bytes_of(&header));
+        bytes.extend_from_slice(bytemuck::cast_slice(&submeshes));
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.vertices));
+        bytes.extend_from_slice(self.indices.as_bytes());
+        bytes
+    }
+
+    ///Fn: From Bytes: Reads a mesh written by to_bytes
+    ///Params: Errors if the data is cut short, from another version or its submeshes run past the indices
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut rest = bytes;
+        let header = read_pod::<MeshHeader>(&mut rest, 1)
+            .context("Mesh data is too short for its header")?
+            .remove(0);
+        ensure!(header.magic == MESH_MAGIC, "Not mesh data");
+        ensure!(
+            header.version == MESH_VERSION,
+            "Mesh data is version {}, expected {}",
+            header.version,
+            MESH_VERSION
+        );
+        let submeshes = read_pod::<SubmeshRaw>(&mut rest, header.submesh_count as usize)
+            .context("Mesh data is cut short in its submeshes")?;
+        let vertices = read_pod::<Vertex>(&mut rest, header.vertex_count as usize)
+            .context("Mesh data is cut short in its vertices")?;
+        let index_count = header.index_count as usize;
+        let indices = match header.index_size {
+            2 => read_pod(&mut rest, index_count).map(Indices::U16),
+            4 => read_pod(&mut rest, index_count).map(Indices::U32),
+            size => bail!("Mesh data has {} byte indices, expected 2 or 4", size),
+        }
+        .context("Mesh data is cut short in its indices")?;
+        let submeshes = submeshes
+            .into_iter()
+            .map(|submesh| {
+                ensure!(
+                    submesh.start <= submesh.end && submesh.end as usize <= index_count,
+                    "Mesh submesh {}..{} is outside its {} indices",
+                    submesh.start,
+                    submesh.end,
+                    index_count
+                );
+                Ok(Submesh {
+                    indices: submesh.start..submesh.end,
+                    base_vertex: submesh.base_vertex,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            vertices,
+            indices,
+            submeshes,
+        })
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        Bounds::from_points(self.vertices.iter().map(|vertex| vertex.position.into()))
+    }
+
+    ///Fn: Pentagon: A flat pentagon facing +Z, half a unit across
+    pub fn pentagon() -> Self {
+        Self::new(
+            PENTAGON_VERTICES.to_vec(),
+            PENTAGON_INDICES.iter().map(|&index| index as u32).collect(),
+            Vec::new(),
+        )
+    }
+
+    ///Fn: Cube: A unit cube around the origin, with its own normals and texture on each face
+    pub fn cube() -> Self {
+        // Each face's normal with the two axes across it, which turn anticlockwise seen from outside
+        let faces = [
+            (Vec3::X, -Vec3::Z, Vec3::Y),
+            (-Vec3::X, Vec3::Z, Vec3::Y),
+            (Vec3::Y, Vec3::X, -Vec3::Z),
+            (-Vec3::Y, Vec3::X, Vec3::Z),
+            (Vec3::Z, Vec3::X, Vec3::Y),
+            (-Vec3::Z, -Vec3::X, Vec3::Y),
+        ];
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+        let mut vertices = Vec::with_capacity(faces.len() * corners.len());
+        let mut indices = Vec::with_capacity(faces.len() * 6);
+        for (normal, u, v) in faces.iter().copied() {
+            let first = vertices.len() as u32;
+            for &(x, y) in &corners {
+                vertices.push(Vertex {
+                    position: ((normal + u * x + v * y) * 0.5).into(),
+                    tex_coords: [(x + 1.0) * 0.5, (1.0 - y) * 0.5],
+                    normal: normal.into(),
+                });
+            }
+            indices.extend([0, 1, 2, 0, 2, 3].iter().map(|index| first + index));
+        }
+        Self::new(vertices, indices, Vec::new())
+    }
+}
+
+///Fn: Read Pod: Copies count values off the front of bytes, which needn't be aligned for them
+fn read_pod<T: bytemuck::Pod>(bytes: &mut &[u8], count: usize) -> Result<Vec<T>> {
+    let size = count
+        .checked_mul(mem::size_of::<T>())
+        .filter(|&size| size <= bytes.len())
+        .context("Ran out of data")?;
+    let mut values = vec![T::zeroed(); count];
+    bytemuck::cast_slice_mut(&mut values).copy_from_slice(&bytes[..size]);
+    *bytes = &bytes[size..];
+    Ok(values)
+}
+
+/// Vertices and indices on the GPU, with the ranges they're drawn in
+pub struct Mesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub index_format: wgpu::IndexFormat,
+    pub submeshes: Vec<Submesh>,
+    pub bounds: Bounds,
+}
+
+impl Mesh {
+    ///Fn: New: Uploads the vertices and indices
+    ///Params: name labels the buffers
+    pub fn new(device: &wgpu::Device, name: &str, data: &MeshData) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(&data.vertices),
+            usage: wgpu::BufferUsage::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", name)),
+            contents: data.indices.as_bytes(),
+            usage: wgpu::BufferUsage::INDEX,
+        });
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_format: data.indices.format(),
+            submeshes: data.submeshes.clone(),
+            bounds: data.bounds(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Fn: Grid: A flat grid of columns by rows vertices, two triangles per cell
+    fn grid(columns: u32, rows: u32) -> MeshData {
+        let mut vertices = Vec::with_capacity((columns * rows) as usize);
+        for row in 0..rows {
+            for column in 0..columns {
+                vertices.push(Vertex {
+                    position: [column as f32, 0.0, row as f32],
+                    tex_coords: [0.0; 2],
+                    normal: [0.0, 1.0, 0.0],
+                });
+            }
+        }
+        let mut indices = Vec::new();
+        for row in 0..rows - 1 {
+            for column in 0..columns - 1 {
+                let corner = row * columns + column;
+                indices.extend_from_slice(&[
+                    corner,
+                    corner + columns,
+                    corner + 1,
+                    corner + 1,
+                    corner + columns,
+                    corner + columns + 1,
+                ]);
+            }
+        }
+        MeshData::new(vertices, indices, Vec::new())
+    }
+
+    fn widened(indices: &Indices) -> Vec<u32> {
+        match indices {
+            Indices::U16(indices) => indices.iter().map(|&index| index as u32).collect(),
+            Indices::U32(indices) => indices.clone(),
+        }
+    }
+
+    #[test]
+    fn small_meshes_use_u16_indices() {
+        // 65536 vertices is the most a u16 can index
+        let mesh = grid(256, 256);
+        assert_eq!(mesh.indices.format(), wgpu::IndexFormat::Uint16);
+        let indices = widened(&mesh.indices);
+        assert_eq!(mesh.indices.as_bytes().len(), indices.len() * 2);
+        assert_eq!(indices.iter().max(), Some(&u16::MAX.into()));
+    }
+
+    #[test]
+    fn large_meshes_use_u32_indices() {
+        let mesh = grid(300, 300);
+        assert!(mesh.vertices.len() > u16::MAX as usize + 1);
+        assert_eq!(mesh.indices.format(), wgpu::IndexFormat::Uint32);
+        let indices = widened(&mesh.indices);
+        assert_eq!(indices.len(), 299 * 299 * 6);
+        assert_eq!(mesh.indices.as_bytes().len(), indices.len() * 4);
+        // Past what a u16 could hold, so nothing was truncated on the way
+        assert_eq!(
+            indices.iter().max().map(|&index| index as usize),
+            Some(mesh.vertices.len() - 1)
+        );
+        assert_eq!(
+            mesh.submeshes,
+            vec![Submesh {
+                indices: 0..indices.len() as u32,
+                base_vertex: 0,
+            }]
+        );
+        assert_eq!(
+            mesh.bounds(),
+            Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::new(299.0, 0.0, 299.0),
+            }
+        );
+    }
+
+    fn assert_same(a: &MeshData, b: &MeshData) {
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&a.vertices),
+            bytemuck::cast_slice::<_, u8>(&b.vertices)
+        );
+        assert_eq!(a.indices, b.indices);
+        assert_eq!(a.submeshes, b.submeshes);
+    }
+
+    #[test]
+    fn meshes_survive_serialization() {
+        let cube = MeshData::cube();
+        assert_same(&MeshData::from_bytes(&cube.to_bytes()).unwrap(), &cube);
+        let mut large = grid(300, 300);
+        large.submeshes.push(Submesh {
+            indices: 6..12,
+            base_vertex: 3,
+        });
+        let read = MeshData::from_bytes(&large.to_bytes()).unwrap();
+        assert_eq!(read.indices.format(), wgpu::IndexFormat::Uint32);
+        assert_same(&read, &large);
+    }
+
+    #[test]
+    fn bad_mesh_data_is_an_error() {
+        let bytes = MeshData::cube().to_bytes();
+        assert!(MeshData::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(MeshData::from_bytes(&bytes[..4]).is_err());
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(MeshData::from_bytes(&wrong_magic).is_err());
+        // A submesh ending past the last index
+        let mut overrun = bytes;
+        let end = mem::size_of::<MeshHeader>() + 4;
+        overrun[end..end + 4].copy_from_slice(&1000u32.to_ne_bytes());
+        assert!(MeshData::from_bytes(&overrun).is_err());
+    }
+
+    #[test]
+    fn cube_faces_point_outwards() {
+        let cube = MeshData::cube();
+        assert_eq!(cube.vertices.len(), 24);
+        assert_eq!(
+            cube.bounds(),
+            Bounds {
+                min: Vec3::splat(-0.5),
+                max: Vec3::splat(0.5),
+            }
+        );
+        let indices = match &cube.indices {
+            Indices::U16(indices) => indices,
+            Indices::U32(_) => panic!("the cube should use u16 indices"),
+        };
+        for triangle in indices.chunks(3) {
+            let [a, b, c] =
+                [0, 1, 2].map(|i| Vec3::from(cube.vertices[triangle[i] as usize].position));
+            let normal = Vec3::from(cube.vertices[triangle[0] as usize].normal);
+            // Anticlockwise seen from outside, which is front facing
+            assert!((b - a).cross(c - a).dot(normal) > 0.0);
+            assert!(a.dot(normal) > 0.0);
+        }
+    }
+}
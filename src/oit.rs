@@ -0,0 +1,287 @@
+use crate::{antialiasing, bundle::Assets, camera::DepthMode, pipeline, texture};
+
+const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+/// Weighted blended order-independent transparency. Transparent geometry is
+/// drawn unsorted into an accumulation and a revealage target, which are then
+/// composited over the opaque result in a single full-screen pass.
+pub struct WeightedBlendedOit {
+    sample_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    msaa_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    accum: texture::Texture,
+    revealage: texture::Texture,
+    msaa_accum: Option<texture::Texture>,
+    msaa_revealage: Option<texture::Texture>,
+    accum_bind_group: wgpu::BindGroup,
+    revealage_bind_group: wgpu::BindGroup,
+}
+
+impl WeightedBlendedOit {
+    ///Fn: New: Builds the accumulation pipelines from the scene's draw shader
+    ///Params: layout, vertex_layouts and module are the ones used by the forward render pipeline
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        assets: &Assets,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        layout: &wgpu::PipelineLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+        module: &wgpu::ShaderModule,
+        sample_count: u32,
+        depth_mode: DepthMode,
+    ) -> Self {
+        let accumulation_pipeline = |sample_count, label| {
+            pipeline::create_render_pipeline_with_fragment(
+                device,
+                layout,
+                &[
+                    wgpu::ColorTargetState {
+                        format: ACCUM_FORMAT,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                        }),
+                        write_mask: wgpu::ColorWrite::ALL,
+                    },
+                    // Multiplies the destination by (1 - alpha) of every fragment
+                    wgpu::ColorTargetState {
+                        format: REVEALAGE_FORMAT,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::Zero,
+                                dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent::REPLACE,
+                        }),
+                        write_mask: wgpu::ColorWrite::ALL,
+                    },
+                ],
+                Some(pipeline::depth_stencil_state(
+                    false,
+                    depth_mode.compare(wgpu::CompareFunction::LessEqual),
+                )),
+                vertex_layouts,
+                module,
+                "oit_fs",
+                sample_count,
+                label,
+            )
+        };
+        let pipeline = accumulation_pipeline(1, "OIT Accumulation Pipeline");
+        let msaa_pipeline = accumulation_pipeline(
+            antialiasing::MSAA_SAMPLE_COUNT,
+            "OIT Multisampled Accumulation Pipeline",
+        );
+
+        let texture_bind_group_layout = pipeline::create_texture_bind_group_layout(
+            device,
+            wgpu::ShaderStage::FRAGMENT,
+            "oit_texture_bind_group_layout",
+        );
+        let composite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("OIT Composite Pipeline Layout"),
+            bind_group_layouts: &[&texture_bind_group_layout, &texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("OIT Composite Shader"),
+            source: wgpu::ShaderSource::Wgsl(pipeline::fullscreen_wgsl(
+                assets,
+                &assets.shader(
+                    "oit_composite.wgsl",
+                    include_str!("../shader/oit_composite.wgsl"),
+                ),
+            )),
+            flags: wgpu::ShaderFlags::default(),
+        });
+        let composite_pipeline = pipeline::create_render_pipeline(
+            device,
+            &composite_layout,
+            &[pipeline::blended_color_target(sc_desc.format)],
+            None,
+            &[],
+            &composite_module,
+            1,
+            "OIT Composite Pipeline",
+        );
+
+        let (accum, revealage, msaa_accum, msaa_revealage) =
+            Self::create_targets(device, sc_desc, sample_count);
+        let (accum_bind_group, revealage_bind_group) =
+            Self::create_bind_groups(device, &texture_bind_group_layout, &accum, &revealage);
+
+        Self {
+            sample_count,
+            pipeline,
+            msaa_pipeline,
+            composite_pipeline,
+            texture_bind_group_layout,
+            accum,
+            revealage,
+            msaa_accum,
+            msaa_revealage,
+            accum_bind_group,
+            revealage_bind_group,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
+    ) -> (
+        texture::Texture,
+        texture::Texture,
+        Option<texture::Texture>,
+        Option<texture::Texture>,
+    ) {
+        let accum =
+            texture::Texture::create_render_target(device, sc_desc, ACCUM_FORMAT, 1, "oit_accum");
+        let revealage = texture::Texture::create_render_target(
+            device,
+            sc_desc,
+            REVEALAGE_FORMAT,
+            1,
+            "oit_revealage",
+        );
+        if sample_count > 1 {
+            let msaa_accum = texture::Texture::create_render_target(
+                device,
+                sc_desc,
+                ACCUM_FORMAT,
+                sample_count,
+                "oit_msaa_accum",
+            );
+            let msaa_revealage = texture::Texture::create_render_target(
+                device,
+                sc_desc,
+                REVEALAGE_FORMAT,
+                sample_count,
+                "oit_msaa_revealage",
+            );
+            (accum, revealage, Some(msaa_accum), Some(msaa_revealage))
+        } else {
+            (accum, revealage, None, None)
+        }
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        accum: &texture::Texture,
+        revealage: &texture::Texture,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        (
+            pipeline::create_texture_bind_group(device, layout, accum, "oit_accum_bind_group"),
+            pipeline::create_texture_bind_group(
+                device,
+                layout,
+                revealage,
+                "oit_revealage_bind_group",
+            ),
+        )
+    }
+
+    ///Fn: Resize: Recreates the targets for the new size and forward sample count
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
+    ) {
+        let (accum, revealage, msaa_accum, msaa_revealage) =
+            Self::create_targets(device, sc_desc, sample_count);
+        let (accum_bind_group, revealage_bind_group) =
+            Self::create_bind_groups(device, &self.texture_bind_group_layout, &accum, &revealage);
+        self.sample_count = sample_count;
+        self.accum = accum;
+        self.revealage = revealage;
+        self.msaa_accum = msaa_accum;
+        self.msaa_revealage = msaa_revealage;
+        self.accum_bind_group = accum_bind_group;
+        self.revealage_bind_group = revealage_bind_group;
+    }
+
+    ///Fn: Begin Accumulation Pass: Clears the targets and starts the transparent pass with its pipeline set
+    ///Params: depth_view holds the opaque depth, multisampled must match it and the forward sample count
+    pub fn begin_accumulation_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &'a wgpu::TextureView,
+        multisampled: bool,
+    ) -> wgpu::RenderPass<'a> {
+        let attachment =
+            |target: &'a texture::Texture, msaa_target: &'a Option<texture::Texture>, clear| {
+                let ops = wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: true,
+                };
+                match msaa_target {
+                    Some(msaa_target) if multisampled => wgpu::RenderPassColorAttachment {
+                        view: &msaa_target.view,
+                        resolve_target: Some(&target.view),
+                        ops,
+                    },
+                    _ => wgpu::RenderPassColorAttachment {
+                        view: &target.view,
+                        resolve_target: None,
+                        ops,
+                    },
+                }
+            };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Accumulation Pass"),
+            color_attachments: &[
+                attachment(&self.accum, &self.msaa_accum, wgpu::Color::TRANSPARENT),
+                attachment(&self.revealage, &self.msaa_revealage, wgpu::Color::WHITE),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        if multisampled && self.sample_count > 1 {
+            render_pass.set_pipeline(&self.msaa_pipeline);
+        } else {
+            render_pass.set_pipeline(&self.pipeline);
+        }
+        render_pass
+    }
+
+    ///Fn: Composite: Blends the resolved transparency over the opaque image in target_view
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, target_view: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Composite Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &self.accum_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.revealage_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -1 +1,235 @@
+use std::borrow::Cow;
 
+use crate::{bundle::Assets, texture};
+
+///Fn: Create Render Pipeline: Builds a triangle list pipeline with the repo defaults
+///Params: depth_stencil is None for passes that don't touch the depth buffer
+#[allow(clippy::too_many_arguments)]
+pub fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_targets: &[wgpu::ColorTargetState],
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    module: &wgpu::ShaderModule,
+    sample_count: u32,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    create_render_pipeline_with_fragment(
+        device,
+        layout,
+        color_targets,
+        depth_stencil,
+        vertex_layouts,
+        module,
+        "main_fs",
+        sample_count,
+        label,
+    )
+}
+
+///Fn: Create Render Pipeline With Fragment: Same as create_render_pipeline with another fragment entry point
+///Params: Lets one shader module hold several fragment variants sharing the same vertex stage
+#[allow(clippy::too_many_arguments)]
+pub fn create_render_pipeline_with_fragment(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_targets: &[wgpu::ColorTargetState],
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    module: &wgpu::ShaderModule,
+    fragment_entry: &str,
+    sample_count: u32,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "main_vs",
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: fragment_entry,
+            targets: color_targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLAMPING
+            clamp_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil,
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+    })
+}
+
+///Fn: Create Depth Only Pipeline: Used to lay down depth before the colour passes
+///Params: The shader's depth_fs has no colour output and only decides which fragments are kept
+pub fn create_depth_only_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    module: &wgpu::ShaderModule,
+    depth_compare: wgpu::CompareFunction,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: "main_vs",
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "depth_fs",
+            targets: &[],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            clamp_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(depth_stencil_state(true, depth_compare)),
+        multisample: wgpu::MultisampleState::default(),
+    })
+}
+
+///Fn: Depth Stencil State: Depth testing against Texture::DEPTH_FORMAT without stencil
+pub fn depth_stencil_state(
+    depth_write_enabled: bool,
+    depth_compare: wgpu::CompareFunction,
+) -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format: texture::Texture::DEPTH_FORMAT,
+        depth_write_enabled,
+        depth_compare,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    }
+}
+
+///Fn: Fullscreen WGSL: Puts the full-screen triangle's vertex stage from fullscreen.wgsl in front of fragment
+///Params: fragment's main_fs takes the VertexOutput it declares
+pub fn fullscreen_wgsl(assets: &Assets, fragment: &str) -> Cow<'static, str> {
+    Cow::Owned(format!(
+        "{}{}",
+        assets.shader("fullscreen.wgsl", include_str!("../shader/fullscreen.wgsl")),
+        fragment
+    ))
+}
+
+///Fn: Create Fullscreen Pipeline: Builds a pipeline that draws a single screen covering triangle
+///Params: The module is expected to be built from fullscreen_wgsl, whose main_vs generates the triangle
+pub fn create_fullscreen_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    module: &wgpu::ShaderModule,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    create_render_pipeline(
+        device,
+        layout,
+        &[color_target(color_format)],
+        None,
+        &[],
+        module,
+        1,
+        label,
+    )
+}
+
+///Fn: Blended Color Target: A colour target that is alpha blended over what's already there
+pub fn blended_color_target(format: wgpu::TextureFormat) -> wgpu::ColorTargetState {
+    wgpu::ColorTargetState {
+        format,
+        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+        write_mask: wgpu::ColorWrite::ALL,
+    }
+}
+
+///Fn: Color Target: An opaque colour target that replaces whatever was there
+pub fn color_target(format: wgpu::TextureFormat) -> wgpu::ColorTargetState {
+    wgpu::ColorTargetState {
+        format,
+        blend: Some(wgpu::BlendState {
+            color: wgpu::BlendComponent::REPLACE,
+            alpha: wgpu::BlendComponent::REPLACE,
+        }),
+        write_mask: wgpu::ColorWrite::ALL,
+    }
+}
+
+///Fn: Create Texture Bind Group Layout: A sampled 2D texture at binding 0 and its sampler at binding 1
+pub fn create_texture_bind_group_layout(
+    device: &wgpu::Device,
+    visibility: wgpu::ShaderStage,
+    label: &str,
+) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility,
+                ty: wgpu::BindingType::Sampler {
+                    comparison: false,
+                    filtering: true,
+                },
+                count: None,
+            },
+        ],
+        label: Some(label),
+    })
+}
+
+///Fn: Create Texture Bind Group: Binds a Texture against create_texture_bind_group_layout
+pub fn create_texture_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &texture::Texture,
+    label: &str,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some(label),
+    })
+}
@@ -1 +1,33 @@
+/// Which lighting path the scene is drawn with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RenderPath {
+    Forward,
+    Deferred,
+}
 
+impl RenderPath {
+    pub fn next(self) -> Self {
+        match self {
+            RenderPath::Forward => RenderPath::Deferred,
+            RenderPath::Deferred => RenderPath::Forward,
+        }
+    }
+}
+
+/// How alpha blended materials are composited over the opaque scene
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransparencyMode {
+    /// Instances sorted back to front and blended in order
+    Sorted,
+    /// Weighted blended order-independent transparency
+    WeightedBlended,
+}
+
+impl TransparencyMode {
+    pub fn next(self) -> Self {
+        match self {
+            TransparencyMode::Sorted => TransparencyMode::WeightedBlended,
+            TransparencyMode::WeightedBlended => TransparencyMode::Sorted,
+        }
+    }
+}
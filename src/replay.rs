@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::DepthMode,
+    input::{InputBindings, InputEvent},
+    scene_file::{SceneFile, SCENE_VERSION},
+    timer::FrameTime,
+};
+
+/// Bumped whenever the file layout or the meaning of recorded input changes
+pub const RECORDING_VERSION: u32 = 3;
+
+/// Everything that reached the InputMap during one frame, with the frame's timing
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub time: FrameTime,
+    /// Window size in pixels, which the camera aspect and every target depend on
+    pub window_size: [u32; 2],
+    pub events: Vec<InputEvent>,
+}
+
+/// A run of input and frame timings that replays to the same frames.
+/// The startup settings, bindings and scene are stored too, as the same events mean something
+/// else under different ones.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub version: u32,
+    pub depth_mode: DepthMode,
+    pub bindings: InputBindings,
+    /// The scene as it was loaded, as the file it came from may have changed or been saved over since
+    pub scene: SceneFile,
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl Recording {
+    pub fn new(depth_mode: DepthMode, bindings: InputBindings, scene: SceneFile) -> Self {
+        Self {
+            version: RECORDING_VERSION,
+            depth_mode,
+            bindings,
+            scene,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn push_frame(&mut self, time: FrameTime, window_size: [u32; 2], events: Vec<InputEvent>) {
+        self.frames.push(RecordedFrame {
+            time,
+            window_size,
+            events,
+        });
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let source = ron::ser::to_string(self).context("Couldn't serialize the recording")?;
+        std::fs::write(path, source).with_context(|| format!("Couldn't write {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        let recording: Self = ron::de::from_str(&source)
+            .with_context(|| format!("Couldn't parse {}", path.display()))?;
+        ensure!(
+            recording.version == RECORDING_VERSION,
+            "{} is a version {} recording, this build replays version {}",
+            path.display(),
+            recording.version,
+            RECORDING_VERSION
+        );
+        ensure!(
+            recording.scene.version == SCENE_VERSION,
+            "{} holds a version {} scene, this build loads version {}",
+            path.display(),
+            recording.scene.version,
+            SCENE_VERSION
+        );
+        Ok(recording)
+    }
+}
+
+/// Steps through a Recording one frame at a time, in place of live input and the FrameTimer
+pub struct Replay {
+    recording: Recording,
+    next: usize,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        Self { recording, next: 0 }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    ///Fn: Next Frame: The next recorded frame, None once they've all been played
+    pub fn next_frame(&mut self) -> Option<&RecordedFrame> {
+        let frame = self.recording.frames.get(self.next)?;
+        self.next += 1;
+        Some(frame)
+    }
+}
@@ -0,0 +1,362 @@
+use glam::{Mat4, Quat, Vec3};
+
+/// Translation, rotation and scale relative to a node's parent
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_translation_rotation(translation: Vec3, rotation: Quat) -> Self {
+        Self {
+            translation,
+            rotation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn to_matrix(self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Handle to a node in its SceneGraph. A removed node's slot is reused under a new
+/// generation, so old handles to it can't reach the new node.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+/// Something a node places in the world. The indices are the renderer's, the graph only
+/// reports which attachments moved.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Attachment {
+    /// Drawn as one instance of the mesh batch with this index
+    Mesh(usize),
+    /// Positions the point light with this index
+    Light(usize),
+    /// Places the camera of the view with this index, looking down the node's -Z
+    Camera(usize),
+}
+
+struct Node {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    local: Transform,
+    /// Parent's world matrix times local, as of the last update
+    world: Mat4,
+    /// The local transform or parent changed since the last update
+    dirty: bool,
+    attachments: Vec<Attachment>,
+}
+
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+/// A hierarchy of nodes with local transforms. World matrices are cached and only
+/// recomputed for nodes that changed, or whose ancestors did, since the last update.
+#[derive(Default)]
+pub struct SceneGraph {
+    slots: Vec<Slot>,
+    /// Slots of removed nodes, reused before the graph grows
+    free: Vec<usize>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    ///Fn: Contains: Whether the node exists, false once it's been removed
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.slots
+            .get(id.index)
+            .is_some_and(|slot| slot.generation == id.generation && slot.node.is_some())
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        assert!(self.contains(id), "{:?} was removed from the scene", id);
+        self.slots[id.index].node.as_ref().unwrap()
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        assert!(self.contains(id), "{:?} was removed from the scene", id);
+        self.slots[id.index].node.as_mut().unwrap()
+    }
+
+    ///Fn: Nodes: Every node still in the graph with its handle, in slot order
+    fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = NodeId {
+                index,
+                generation: slot.generation,
+            };
+            slot.node.as_ref().map(|node| (id, node))
+        })
+    }
+
+    ///Fn: Add Node: Adds a node under parent, or as a root without one
+    pub fn add_node(&mut self, parent: Option<NodeId>, local: Transform) -> NodeId {
+        let node = Node {
+            parent,
+            children: Vec::new(),
+            local,
+            world: Mat4::IDENTITY,
+            dirty: true,
+            attachments: Vec::new(),
+        };
+        let id = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        };
+        if let Some(parent) = parent {
+            self.node_mut(parent).children.push(id);
+        }
+        id
+    }
+
+    pub fn attach(&mut self, node: NodeId, attachment: Attachment) {
+        self.node_mut(node).attachments.push(attachment);
+    }
+
+    ///Fn: Remove Node: Removes a node and its whole subtree
+    ///Params: Returns every removed node with its attachments, so their owners can release them
+    pub fn remove_node(&mut self, id: NodeId) -> Vec<(NodeId, Vec<Attachment>)> {
+        if let Some(parent) = self.node(id).parent {
+            self.node_mut(parent).children.retain(|&child| child != id);
+        }
+        let mut removed = Vec::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let slot = &mut self.slots[id.index];
+            let node = slot.node.take().unwrap();
+            slot.generation += 1;
+            self.free.push(id.index);
+            stack.extend(node.children);
+            removed.push((id, node.attachments));
+        }
+        removed
+    }
+
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self.node(node).parent
+    }
+
+    ///Fn: Set Parent: Moves a node and its subtree under a new parent, keeping its local transform
+    ///Params: Refuses, returning false, to move a node under itself or one of its descendants
+    pub fn set_parent(&mut self, node: NodeId, parent: Option<NodeId>) -> bool {
+        let mut ancestor = parent;
+        while let Some(id) = ancestor {
+            if id == node {
+                return false;
+            }
+            ancestor = self.node(id).parent;
+        }
+        if let Some(old_parent) = self.node(node).parent {
+            self.node_mut(old_parent)
+                .children
+                .retain(|&child| child != node);
+        }
+        if let Some(parent) = parent {
+            self.node_mut(parent).children.push(node);
+        }
+        let node = self.node_mut(node);
+        node.parent = parent;
+        node.dirty = true;
+        true
+    }
+
+    ///Fn: Set Local: Changes a node's transform, its subtree is recomputed on the next update
+    pub fn set_local(&mut self, node: NodeId, local: Transform) {
+        let node = self.node_mut(node);
+        if node.local != local {
+            node.local = local;
+            node.dirty = true;
+        }
+    }
+
+    ///Fn: World Matrix: The node's cached world matrix, as of the last update
+    pub fn world_matrix(&self, node: NodeId) -> Mat4 {
+        self.node(node).world
+    }
+
+    ///Fn: Update World Matrices: Recomputes every dirty node and everything below it
+    ///Params: Returns the nodes whose world matrix changed, parents before children
+    pub fn update_world_matrices(&mut self) -> Vec<NodeId> {
+        let mut changed = Vec::new();
+        // Depth first from every root, carrying whether an ancestor moved
+        let mut stack = self
+            .nodes()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(id, _)| (id, false))
+            .collect::<Vec<_>>();
+        stack.reverse();
+        while let Some((id, parent_changed)) = stack.pop() {
+            let node = self.node(id);
+            let recompute = parent_changed || node.dirty;
+            if recompute {
+                let parent_world = node
+                    .parent
+                    .map_or(Mat4::IDENTITY, |parent| self.node(parent).world);
+                let node = self.node_mut(id);
+                node.world = parent_world * node.local.to_matrix();
+                node.dirty = false;
+                changed.push(id);
+            }
+            let node = self.node(id);
+            stack.extend(node.children.iter().rev().map(|&child| (child, recompute)));
+        }
+        changed
+    }
+
+    pub fn attachments(&self, node: NodeId) -> &[Attachment] {
+        &self.node(node).attachments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(graph: &SceneGraph, node: NodeId) -> Vec3 {
+        graph.world_matrix(node).w_axis.truncate()
+    }
+
+    #[test]
+    fn children_follow_their_parent() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, Transform::from_translation(Vec3::X));
+        let child = graph.add_node(Some(root), Transform::from_translation(Vec3::Y * 2.0));
+        graph.update_world_matrices();
+        assert_eq!(translation(&graph, child), Vec3::new(1.0, 2.0, 0.0));
+
+        // A node parented under one that has since moved picks up where it is now
+        graph.set_local(root, Transform::from_translation(Vec3::X * 5.0));
+        let other = graph.add_node(None, Transform::from_translation(Vec3::Z));
+        graph.update_world_matrices();
+        assert!(graph.set_parent(other, Some(root)));
+        graph.update_world_matrices();
+        assert_eq!(graph.parent(other), Some(root));
+        assert_eq!(translation(&graph, other), Vec3::new(5.0, 0.0, 1.0));
+        assert_eq!(translation(&graph, child), Vec3::new(5.0, 2.0, 0.0));
+
+        // And drops it again when moved back to the root, keeping its local transform
+        assert!(graph.set_parent(other, None));
+        graph.update_world_matrices();
+        assert_eq!(translation(&graph, other), Vec3::Z);
+    }
+
+    #[test]
+    fn cycles_are_refused() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, Transform::IDENTITY);
+        let child = graph.add_node(Some(root), Transform::IDENTITY);
+        let grandchild = graph.add_node(Some(child), Transform::IDENTITY);
+        assert!(!graph.set_parent(root, Some(grandchild)));
+        assert!(!graph.set_parent(child, Some(child)));
+        assert_eq!(graph.parent(root), None);
+        assert_eq!(graph.parent(child), Some(root));
+        // Nothing was left half moved, so the whole tree still updates
+        assert_eq!(graph.update_world_matrices(), vec![root, child, grandchild]);
+    }
+
+    #[test]
+    fn removed_nodes_stay_removed() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, Transform::IDENTITY);
+        let child = graph.add_node(Some(root), Transform::IDENTITY);
+        let grandchild = graph.add_node(Some(child), Transform::IDENTITY);
+        graph.attach(grandchild, Attachment::Light(3));
+        let mut removed = graph.remove_node(child);
+        removed.sort_by_key(|(id, _)| *id);
+        assert_eq!(
+            removed,
+            vec![
+                (child, Vec::new()),
+                (grandchild, vec![Attachment::Light(3)])
+            ]
+        );
+        assert!(graph.contains(root));
+        assert!(!graph.contains(child) && !graph.contains(grandchild));
+
+        // The slots are reused, but the old handles don't reach the new nodes
+        let reused = graph.add_node(None, Transform::IDENTITY);
+        let reused_too = graph.add_node(None, Transform::IDENTITY);
+        assert!(![reused, reused_too].contains(&child));
+        assert!(![reused, reused_too].contains(&grandchild));
+        assert!(!graph.contains(child) && !graph.contains(grandchild));
+        assert!(graph.contains(reused) && graph.contains(reused_too));
+        let mut updated = graph.update_world_matrices();
+        updated.sort();
+        let mut expected = vec![root, reused, reused_too];
+        expected.sort();
+        assert_eq!(updated, expected);
+    }
+
+    #[test]
+    fn only_dirty_nodes_are_updated() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, Transform::IDENTITY);
+        let child = graph.add_node(Some(root), Transform::IDENTITY);
+        let grandchild = graph.add_node(Some(child), Transform::IDENTITY);
+        let other = graph.add_node(None, Transform::IDENTITY);
+        assert_eq!(
+            graph.update_world_matrices(),
+            vec![root, child, grandchild, other]
+        );
+        assert!(graph.update_world_matrices().is_empty());
+
+        // Setting the transform a node already has doesn't dirty it
+        graph.set_local(other, Transform::IDENTITY);
+        assert!(graph.update_world_matrices().is_empty());
+
+        graph.set_local(grandchild, Transform::from_translation(Vec3::X));
+        assert_eq!(graph.update_world_matrices(), vec![grandchild]);
+        graph.set_local(child, Transform::from_translation(Vec3::Y));
+        assert_eq!(graph.update_world_matrices(), vec![child, grandchild]);
+        assert_eq!(translation(&graph, grandchild), Vec3::new(1.0, 1.0, 0.0));
+    }
+}
@@ -0,0 +1,544 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bundle::Assets,
+    camera::{Camera, DepthMode, Projection},
+    ecs::{Entity, MaterialHandle, MeshHandle, Name, Parent, ViewCamera, World},
+    instance::InstanceAttributes,
+    light::PointLight,
+    material::{AlphaMode, MaterialUniform},
+    scene::Transform,
+    view,
+};
+
+/// Bumped whenever the file layout or the meaning of a field changes
+pub const SCENE_VERSION: u32 = 1;
+/// Loaded at startup unless --scene names another, and where save_scene writes
+pub const SCENE_PATH: &str = "scenes/default.ron";
+/// Texture files are looked up here
+pub const ASSETS_PATH: &str = "assets";
+/// Meshes by the name scene files use for them, indexed by MeshHandle
+pub const MESH_NAMES: &[&str] = &["pentagon", "cube"];
+
+/// Everything placed in the world, as stored in a scene file.
+/// Views are part of the renderer, nodes only refer to them by label.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneFile {
+    pub version: u32,
+    pub camera: CameraDesc,
+    /// Referred to by name from the nodes, the first is also used for instances spawned at runtime
+    pub materials: Vec<MaterialDesc>,
+    /// Root nodes, each with its children nested inside it
+    pub nodes: Vec<NodeDesc>,
+}
+
+/// The camera the window is drawn from. Its aspect follows the window and its
+/// depth mode is picked at startup.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CameraDesc {
+    #[serde(default = "default_projection")]
+    pub projection: Projection,
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    /// Degrees
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+fn default_projection() -> Projection {
+    Projection::Perspective
+}
+
+impl CameraDesc {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            projection: camera.projection,
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+            fovy: camera.fovy,
+            znear: camera.znear,
+            zfar: camera.zfar,
+        }
+    }
+
+    pub fn to_camera(&self, depth_mode: DepthMode, aspect: f32) -> Camera {
+        Camera {
+            projection: self.projection,
+            depth_mode,
+            eye: self.eye.into(),
+            target: self.target.into(),
+            up: Vec3::Y,
+            aspect,
+            fovy: self.fovy,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+}
+
+/// Where a material's texture comes from
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TextureRef {
+    /// An image file under assets/
+    File(String),
+    /// The output of the view with this label, rendered before anything drawn with it
+    View(String),
+}
+
+/// The view layer a material is drawn on, which views can leave out
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Layer {
+    Default,
+    Foliage,
+    Glass,
+    Screens,
+}
+
+impl Layer {
+    pub fn mask(self) -> u32 {
+        match self {
+            Layer::Default => view::LAYER_DEFAULT,
+            Layer::Foliage => view::LAYER_FOLIAGE,
+            Layer::Glass => view::LAYER_GLASS,
+            Layer::Screens => view::LAYER_SCREENS,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MaterialDesc {
+    pub name: String,
+    pub texture: TextureRef,
+    #[serde(default = "default_tint")]
+    pub tint: [f32; 4],
+    #[serde(default = "default_roughness")]
+    pub roughness: f32,
+    #[serde(default)]
+    pub metallic: f32,
+    #[serde(default = "default_alpha_mode")]
+    pub alpha_mode: AlphaMode,
+    #[serde(default = "default_layer")]
+    pub layer: Layer,
+}
+
+fn default_tint() -> [f32; 4] {
+    MaterialUniform::default().tint
+}
+
+fn default_roughness() -> f32 {
+    MaterialUniform::default().roughness
+}
+
+fn default_alpha_mode() -> AlphaMode {
+    AlphaMode::Opaque
+}
+
+fn default_layer() -> Layer {
+    Layer::Default
+}
+
+impl MaterialDesc {
+    ///Fn: Uniform: The shading parameters, the alpha cutoff is filled in from the alpha mode by Material::new
+    pub fn uniform(&self) -> MaterialUniform {
+        MaterialUniform {
+            tint: self.tint,
+            roughness: self.roughness,
+            metallic: self.metallic,
+            ..Default::default()
+        }
+    }
+}
+
+/// A Transform as written in scene files, with the rotation as angles
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransformDesc {
+    pub translation: [f32; 3],
+    /// Degrees about X, Y and Z. The node is rolled about Z, then pitched about X, then turned about Y.
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl TransformDesc {
+    fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl From<TransformDesc> for Transform {
+    fn from(desc: TransformDesc) -> Self {
+        let [x, y, z] = desc.rotation;
+        Transform {
+            translation: desc.translation.into(),
+            rotation: Quat::from_rotation_y(y.to_radians())
+                * Quat::from_rotation_x(x.to_radians())
+                * Quat::from_rotation_z(z.to_radians()),
+            scale: desc.scale.into(),
+        }
+    }
+}
+
+impl From<Transform> for TransformDesc {
+    fn from(transform: Transform) -> Self {
+        // Undoes Y * X * Z, whose middle row is [cos x sin z, cos x cos z, -sin x]
+        let m = glam::Mat3::from_quat(transform.rotation);
+        // atan2 rather than asin, which loses precision close to straight up or down
+        let x = (-m.z_axis.y).atan2(m.z_axis.x.hypot(m.z_axis.z));
+        let (y, z) = if m.z_axis.y.abs() < 0.99999 {
+            (m.z_axis.x.atan2(m.z_axis.z), m.x_axis.y.atan2(m.y_axis.y))
+        } else {
+            // Looking straight up or down turning and rolling are the same, so it's all turn
+            ((-m.x_axis.z).atan2(m.x_axis.x), 0.0)
+        };
+        Self {
+            translation: transform.translation.into(),
+            // Adding zero turns -0 into 0, which reads better in the file
+            rotation: [
+                x.to_degrees() + 0.0,
+                y.to_degrees() + 0.0,
+                z.to_degrees() + 0.0,
+            ],
+            scale: transform.scale.into(),
+        }
+    }
+}
+
+/// A mesh drawn at the node
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MeshDesc {
+    /// One of MESH_NAMES
+    pub mesh: String,
+    /// The name of one of the scene's materials
+    pub material: String,
+    #[serde(default, skip_serializing_if = "is_default_attributes")]
+    pub attributes: InstanceAttributes,
+}
+
+fn is_default_attributes(attributes: &InstanceAttributes) -> bool {
+    *attributes == InstanceAttributes::default()
+}
+
+/// A point light at the node
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LightDesc {
+    pub color: [f32; 3],
+    pub radius: f32,
+    pub intensity: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeDesc {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "TransformDesc::is_identity")]
+    pub transform: TransformDesc,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<MeshDesc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<LightDesc>,
+    /// Label of the view whose camera the node places, looking down its -Z
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<NodeDesc>,
+}
+
+impl NodeDesc {
+    pub fn new(name: &str, transform: Transform) -> Self {
+        Self {
+            name: name.to_string(),
+            transform: transform.into(),
+            mesh: None,
+            light: None,
+            view: None,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl SceneFile {
+    pub fn new(camera: CameraDesc, materials: Vec<MaterialDesc>, nodes: Vec<NodeDesc>) -> Self {
+        Self {
+            version: SCENE_VERSION,
+            camera,
+            materials,
+            nodes,
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)
+                .with_context(|| format!("Couldn't create {}", directory.display()))?;
+        }
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+            .context("Couldn't serialize the scene")?;
+        std::fs::write(path, source).with_context(|| format!("Couldn't write {}", path.display()))
+    }
+
+    ///Fn: Load: Reads the scene at path, loose or from the bundle
+    pub fn load(assets: &Assets, path: &str) -> Result<Self> {
+        let source = assets.read_to_string(path)?;
+        let scene: Self =
+            ron::de::from_str(&source).with_context(|| format!("Couldn't parse {}", path))?;
+        ensure!(
+            scene.version == SCENE_VERSION,
+            "{} is a version {} scene, this build loads version {}",
+            path,
+            scene.version,
+            SCENE_VERSION
+        );
+        Ok(scene)
+    }
+
+    ///Fn: Spawn: Adds an entity for every node, named after it and parented as it's nested
+    ///Params: views are the view labels in order. Unknown meshes, materials and views are left off with a warning
+    pub fn spawn(&self, world: &mut World, views: &[&str]) {
+        for node in &self.nodes {
+            self.spawn_node(world, node, None, views);
+        }
+    }
+
+    fn spawn_node(
+        &self,
+        world: &mut World,
+        node: &NodeDesc,
+        parent: Option<Entity>,
+        views: &[&str],
+    ) {
+        let entity = world.spawn((Name(node.name.clone()), Transform::from(node.transform)));
+        if let Some(parent) = parent {
+            world.insert_one(entity, Parent(parent)).unwrap();
+        }
+        if let Some(mesh) = &node.mesh {
+            let mesh_handle = MESH_NAMES.iter().position(|&name| name == mesh.mesh);
+            let material_handle = self
+                .materials
+                .iter()
+                .position(|material| material.name == mesh.material);
+            match (mesh_handle, material_handle) {
+                (Some(mesh_handle), Some(material_handle)) => world
+                    .insert(
+                        entity,
+                        (
+                            MeshHandle(mesh_handle),
+                            MaterialHandle(material_handle),
+                            mesh.attributes,
+                        ),
+                    )
+                    .unwrap(),
+                _ => log::warn!(
+                    "Node {} draws mesh {} with material {}, which the scene doesn't have",
+                    node.name,
+                    mesh.mesh,
+                    mesh.material
+                ),
+            }
+        }
+        if let Some(light) = &node.light {
+            let light = PointLight::new(
+                Vec3::ZERO,
+                light.radius,
+                light.color.into(),
+                light.intensity,
+            );
+            world.insert_one(entity, light).unwrap();
+        }
+        if let Some(view) = &node.view {
+            match views.iter().position(|label| label == view) {
+                Some(index) => world.insert_one(entity, ViewCamera(index)).unwrap(),
+                None => log::warn!(
+                    "Node {} places view {}, which doesn't exist",
+                    node.name,
+                    view
+                ),
+            }
+        }
+        for child in &node.children {
+            self.spawn_node(world, child, Some(entity), views);
+        }
+    }
+
+    ///Fn: From World: Writes every entity with a Transform back out as a node, nested under its parent
+    ///Params: materials are the descriptions MaterialHandles index, views the view labels in order
+    pub fn from_world(
+        world: &World,
+        camera: CameraDesc,
+        materials: Vec<MaterialDesc>,
+        views: &[&str],
+    ) -> Self {
+        let mut children = HashMap::<Option<Entity>, Vec<Entity>>::new();
+        let mut query = world.query::<(Entity, &Transform, Option<&Parent>)>();
+        for (entity, _, parent) in query.iter() {
+            let parent = parent
+                .map(|Parent(parent)| *parent)
+                .filter(|&parent| world.satisfies::<&Transform>(parent));
+            children.entry(parent).or_default().push(entity);
+        }
+        drop(query);
+        // Spawn order, which is close to the order they were authored in
+        for siblings in children.values_mut() {
+            siblings.sort_by_key(|entity| entity.id());
+        }
+        let nodes = node_descs(world, None, &children, &materials, views);
+        Self::new(camera, materials, nodes)
+    }
+}
+
+fn node_descs(
+    world: &World,
+    parent: Option<Entity>,
+    children: &HashMap<Option<Entity>, Vec<Entity>>,
+    materials: &[MaterialDesc],
+    views: &[&str],
+) -> Vec<NodeDesc> {
+    let entities = match children.get(&parent) {
+        Some(entities) => entities,
+        None => return Vec::new(),
+    };
+    entities
+        .iter()
+        .map(|&entity| {
+            let name = match world.get::<&Name>(entity) {
+                Ok(name) => name.0.clone(),
+                Err(_) => format!("entity_{}", entity.id()),
+            };
+            let transform = *world.get::<&Transform>(entity).unwrap();
+            let mut node = NodeDesc::new(&name, transform);
+            let mut drawn = world
+                .query_one::<(&MeshHandle, &MaterialHandle, Option<&InstanceAttributes>)>(entity);
+            if let Ok((mesh, material, attributes)) = drawn.get() {
+                node.mesh = Some(MeshDesc {
+                    mesh: MESH_NAMES[mesh.0].to_string(),
+                    material: materials[material.0].name.clone(),
+                    attributes: attributes.copied().unwrap_or_default(),
+                });
+            }
+            drop(drawn);
+            if let Ok(light) = world.get::<&PointLight>(entity) {
+                node.light = Some(LightDesc {
+                    color: light.color,
+                    radius: light.radius,
+                    intensity: light.intensity,
+                });
+            }
+            if let Ok(view) = world.get::<&ViewCamera>(entity) {
+                node.view = views.get(view.0).map(|label| label.to_string());
+            }
+            node.children = node_descs(world, Some(entity), children, materials, views);
+            node
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Fn: Degrees Apart: The difference between two angles, so -180 and 180 are the same
+    fn degrees_apart(a: f32, b: f32) -> f32 {
+        let apart = (a - b).rem_euclid(360.0);
+        apart.min(360.0 - apart)
+    }
+
+    fn rotation_matrix(transform: Transform) -> glam::Mat3 {
+        glam::Mat3::from_quat(transform.rotation)
+    }
+
+    #[test]
+    fn transforms_survive_the_angles() {
+        for &x in &[-90.0, -89.0, -45.0, 0.0, 30.0, 60.0, 89.0, 90.0] {
+            for &y in &[-170.0, -90.0, 0.0, 45.0, 135.0, 180.0] {
+                for &z in &[-120.0, 0.0, 30.0, 90.0] {
+                    let desc = TransformDesc {
+                        translation: [1.0, -2.0, 3.0],
+                        rotation: [x, y, z],
+                        scale: [1.0, 2.0, 0.5],
+                    };
+                    let transform = Transform::from(desc);
+                    let saved = TransformDesc::from(transform);
+                    let loaded = Transform::from(saved);
+                    assert!(
+                        rotation_matrix(loaded).abs_diff_eq(rotation_matrix(transform), 1e-4),
+                        "{:?} came back as {:?}",
+                        desc.rotation,
+                        saved.rotation
+                    );
+                    assert_eq!(loaded.translation, transform.translation);
+                    assert_eq!(loaded.scale, transform.scale);
+                    // Saving what was loaded writes the same angles again
+                    let resaved = TransformDesc::from(loaded);
+                    for (a, b) in resaved.rotation.iter().zip(&saved.rotation) {
+                        assert!(
+                            degrees_apart(*a, *b) < 0.05,
+                            "{:?} then {:?}",
+                            saved,
+                            resaved
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn straight_up_and_down_keep_their_turn() {
+        for &x in &[-90.0, 90.0] {
+            let desc = TransformDesc {
+                rotation: [x, 30.0, 0.0],
+                ..TransformDesc::default()
+            };
+            let saved = TransformDesc::from(Transform::from(desc));
+            assert!(degrees_apart(saved.rotation[0], x) < 0.05);
+            assert!(degrees_apart(saved.rotation[1], 30.0) < 0.05);
+            assert_eq!(saved.rotation[2], 0.0);
+        }
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let directory = std::env::temp_dir().join(format!("ashen-scene-{}", std::process::id()));
+        let path = directory.join("scene.ron");
+        let path_str = path.to_str().unwrap();
+        let camera = CameraDesc {
+            projection: Projection::Perspective,
+            eye: [0.0, 5.0, 10.0],
+            target: [0.0; 3],
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let mut scene = SceneFile::new(
+            camera,
+            Vec::new(),
+            vec![NodeDesc::new("node", Transform::IDENTITY)],
+        );
+        scene.save(&path).unwrap();
+        let loaded = SceneFile::load(&Assets::Loose, path_str).unwrap();
+        assert_eq!(loaded.nodes[0].name, "node");
+
+        scene.version = SCENE_VERSION + 1;
+        scene.save(&path).unwrap();
+        let error = match SceneFile::load(&Assets::Loose, path_str) {
+            Ok(_) => panic!("a version {} scene loaded", scene.version),
+            Err(e) => e.to_string(),
+        };
+        assert!(error.contains(&format!("version {} scene", SCENE_VERSION + 1)));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
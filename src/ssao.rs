@@ -0,0 +1,420 @@
+use std::num::NonZeroU32;
+
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::{bundle::Assets, camera::Camera, pipeline, texture};
+
+const SSAO_KERNEL_SIZE: usize = 16;
+const SSAO_NOISE_SIZE: u32 = 4;
+const SSAO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniforms {
+    proj: Mat4,
+    inv_proj: Mat4,
+    kernel: [[f32; 4]; SSAO_KERNEL_SIZE],
+    radius: f32,
+    bias: f32,
+    /// Depth of empty background, 1 or 0 with reverse-Z
+    far_depth: f32,
+    _padding: f32,
+}
+
+impl SsaoUniforms {
+    fn new() -> Self {
+        let mut random = Lcg(0x5eed);
+        let mut kernel = [[0.0; 4]; SSAO_KERNEL_SIZE];
+        for (i, sample) in kernel.iter_mut().enumerate() {
+            let direction = Vec3::new(
+                random.next() * 2.0 - 1.0,
+                random.next() * 2.0 - 1.0,
+                random.next(),
+            )
+            .normalize();
+            // Pull the samples in towards the origin so close occluders weigh more
+            let scale = i as f32 / SSAO_KERNEL_SIZE as f32;
+            let scale = 0.1 + 0.9 * scale * scale;
+            let sample_position = direction * random.next() * scale;
+            *sample = [sample_position.x, sample_position.y, sample_position.z, 0.0];
+        }
+
+        Self {
+            proj: Mat4::IDENTITY,
+            inv_proj: Mat4::IDENTITY,
+            kernel,
+            radius: 0.5,
+            bias: 0.025,
+            far_depth: 1.0,
+            _padding: 0.0,
+        }
+    }
+
+    fn update_proj(&mut self, camera: &Camera) {
+        self.proj = camera.build_projection_matrix();
+        self.inv_proj = self.proj.inverse();
+        self.far_depth = camera.depth_mode.far_depth();
+    }
+}
+
+/// Small deterministic generator, so the kernel and noise are the same every run
+struct Lcg(u32);
+
+impl Lcg {
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// Screen-space ambient occlusion computed from the depth prepass and blurred,
+/// ready to be sampled by the scene pass through output_bind_group.
+pub struct Ssao {
+    pub enabled: bool,
+    uniforms: SsaoUniforms,
+    uniform_buffer: wgpu::Buffer,
+    noise_texture: texture::Texture,
+    input_bind_group_layout: wgpu::BindGroupLayout,
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    ssao_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    occlusion: texture::Texture,
+    blurred: texture::Texture,
+    input_bind_group: wgpu::BindGroup,
+    blur_bind_group: wgpu::BindGroup,
+    pub output_bind_group: wgpu::BindGroup,
+    /// Stands in for output_bind_group in views that have no occlusion of their own
+    pub unoccluded_bind_group: wgpu::BindGroup,
+}
+
+impl Ssao {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        assets: &Assets,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        depth_texture: &texture::Texture,
+    ) -> Self {
+        let uniforms = SsaoUniforms::new();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSAO Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let noise_texture = Self::create_noise_texture(device, queue);
+
+        let input_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("ssao_input_bind_group_layout"),
+            });
+        let texture_bind_group_layout = pipeline::create_texture_bind_group_layout(
+            device,
+            wgpu::ShaderStage::FRAGMENT,
+            "ssao_texture_bind_group_layout",
+        );
+
+        let ssao_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Shader"),
+            source: wgpu::ShaderSource::Wgsl(pipeline::fullscreen_wgsl(
+                assets,
+                &assets.shader("ssao.wgsl", include_str!("../shader/ssao.wgsl")),
+            )),
+            flags: wgpu::ShaderFlags::default(),
+        });
+        let blur_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Blur Shader"),
+            source: wgpu::ShaderSource::Wgsl(pipeline::fullscreen_wgsl(
+                assets,
+                &assets.shader("ssao_blur.wgsl", include_str!("../shader/ssao_blur.wgsl")),
+            )),
+            flags: wgpu::ShaderFlags::default(),
+        });
+
+        let ssao_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Pipeline Layout"),
+            bind_group_layouts: &[&input_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let blur_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Blur Pipeline Layout"),
+            bind_group_layouts: &[&texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let ssao_pipeline = pipeline::create_fullscreen_pipeline(
+            device,
+            &ssao_pipeline_layout,
+            SSAO_FORMAT,
+            &ssao_module,
+            "SSAO Pipeline",
+        );
+        let blur_pipeline = pipeline::create_fullscreen_pipeline(
+            device,
+            &blur_pipeline_layout,
+            SSAO_FORMAT,
+            &blur_module,
+            "SSAO Blur Pipeline",
+        );
+
+        let occlusion =
+            texture::Texture::create_render_target(device, sc_desc, SSAO_FORMAT, 1, "ssao");
+        let blurred =
+            texture::Texture::create_render_target(device, sc_desc, SSAO_FORMAT, 1, "ssao_blurred");
+        let input_bind_group = Self::create_input_bind_group(
+            device,
+            &input_bind_group_layout,
+            depth_texture,
+            &noise_texture,
+            &uniform_buffer,
+        );
+        let blur_bind_group = pipeline::create_texture_bind_group(
+            device,
+            &texture_bind_group_layout,
+            &occlusion,
+            "ssao_blur_bind_group",
+        );
+        let output_bind_group = pipeline::create_texture_bind_group(
+            device,
+            &texture_bind_group_layout,
+            &blurred,
+            "ssao_output_bind_group",
+        );
+        let white = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba([255; 4]),
+        ));
+        let white_texture =
+            texture::Texture::from_image(device, queue, &white, Some("ssao_unoccluded")).unwrap();
+        let unoccluded_bind_group = pipeline::create_texture_bind_group(
+            device,
+            &texture_bind_group_layout,
+            &white_texture,
+            "ssao_unoccluded_bind_group",
+        );
+
+        Self {
+            enabled: true,
+            uniforms,
+            uniform_buffer,
+            noise_texture,
+            input_bind_group_layout,
+            texture_bind_group_layout,
+            ssao_pipeline,
+            blur_pipeline,
+            occlusion,
+            blurred,
+            input_bind_group,
+            blur_bind_group,
+            output_bind_group,
+            unoccluded_bind_group,
+        }
+    }
+
+    fn create_noise_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+        let mut random = Lcg(0x0a0a);
+        let noise = (0..SSAO_NOISE_SIZE * SSAO_NOISE_SIZE)
+            .flat_map(|_| {
+                // Random rotations around the view space z axis, stored as unorm
+                let x = random.next();
+                let y = random.next();
+                vec![(x * 255.0) as u8, (y * 255.0) as u8, 127, 255]
+            })
+            .collect::<Vec<_>>();
+
+        let size = wgpu::Extent3d {
+            width: SSAO_NOISE_SIZE,
+            height: SSAO_NOISE_SIZE,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ssao_noise"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &noise,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * SSAO_NOISE_SIZE),
+                rows_per_image: NonZeroU32::new(SSAO_NOISE_SIZE),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        texture::Texture {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    fn create_input_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        depth_texture: &texture::Texture,
+        noise_texture: &texture::Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&noise_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("ssao_input_bind_group"),
+        })
+    }
+
+    ///Fn: Resize: Recreates the occlusion targets and rebinds the new depth texture
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        depth_texture: &texture::Texture,
+    ) {
+        self.occlusion =
+            texture::Texture::create_render_target(device, sc_desc, SSAO_FORMAT, 1, "ssao");
+        self.blurred =
+            texture::Texture::create_render_target(device, sc_desc, SSAO_FORMAT, 1, "ssao_blurred");
+        self.input_bind_group = Self::create_input_bind_group(
+            device,
+            &self.input_bind_group_layout,
+            depth_texture,
+            &self.noise_texture,
+            &self.uniform_buffer,
+        );
+        self.blur_bind_group = pipeline::create_texture_bind_group(
+            device,
+            &self.texture_bind_group_layout,
+            &self.occlusion,
+            "ssao_blur_bind_group",
+        );
+        self.output_bind_group = pipeline::create_texture_bind_group(
+            device,
+            &self.texture_bind_group_layout,
+            &self.blurred,
+            "ssao_output_bind_group",
+        );
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        self.uniforms.update_proj(camera);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniforms]),
+        );
+    }
+
+    ///Fn: Render: Computes and blurs the occlusion from the depth prepass
+    ///Params: When disabled the output is cleared to fully unoccluded instead
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.enabled {
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("SSAO Clear Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &self.blurred.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            return;
+        }
+
+        for (label, view, pipeline, bind_group) in [
+            (
+                "SSAO Pass",
+                &self.occlusion.view,
+                &self.ssao_pipeline,
+                &self.input_bind_group,
+            ),
+            (
+                "SSAO Blur Pass",
+                &self.blurred.view,
+                &self.blur_pipeline,
+                &self.blur_bind_group,
+            ),
+        ] {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+/// Longest frame the simulation will step through, so a stall doesn't teleport the camera
+const MAX_DELTA: f32 = 0.25;
+/// Weight of the newest frame in the smoothed frame rate
+const FPS_SMOOTHING: f32 = 0.1;
+
+/// Timing for a single frame, everything in seconds
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct FrameTime {
+    /// Time since the timer was created
+    pub total: f32,
+    /// Exponentially smoothed frames per second
+    pub fps: f32,
+    /// How many simulation steps to run this frame, 1 unless a fixed timestep is set
+    pub steps: u32,
+    /// Length of each simulation step
+    pub step_delta: f32,
+}
+
+/// Measures frame times, and optionally splits them into fixed simulation steps
+pub struct FrameTimer {
+    start: Instant,
+    last: Instant,
+    fps: f32,
+    /// Seconds per simulation step, None to step once per frame by the frame's delta
+    pub fixed_timestep: Option<f32>,
+    accumulator: f32,
+}
+
+impl FrameTimer {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            fps: 0.0,
+            fixed_timestep: None,
+            accumulator: 0.0,
+        }
+    }
+
+    ///Fn: Tick: Marks the start of a new frame and returns its timing
+    pub fn tick(&mut self) -> FrameTime {
+        let now = Instant::now();
+        let delta = (now - self.last).as_secs_f32().min(MAX_DELTA);
+        self.last = now;
+
+        if delta > 0.0 {
+            let fps = 1.0 / delta;
+            self.fps = if self.fps == 0.0 {
+                fps
+            } else {
+                self.fps + (fps - self.fps) * FPS_SMOOTHING
+            };
+        }
+
+        let (steps, step_delta) = match self.fixed_timestep {
+            Some(timestep) => {
+                self.accumulator += delta;
+                let steps = (self.accumulator / timestep) as u32;
+                self.accumulator -= steps as f32 * timestep;
+                (steps, timestep)
+            }
+            None => (1, delta),
+        };
+
+        FrameTime {
+            total: (now - self.start).as_secs_f32(),
+            fps: self.fps,
+            steps,
+            step_delta,
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    bundle::Assets,
+    camera::{Camera, CameraUniform},
+    cluster::ClusteredLighting,
+    draw_list::DrawItem,
+    pipeline, texture,
+};
+
+/// Layers instances are drawn on, a view only draws the layers in its mask
+pub const LAYER_DEFAULT: u32 = 1 << 0;
+pub const LAYER_FOLIAGE: u32 = 1 << 1;
+pub const LAYER_GLASS: u32 = 1 << 2;
+pub const LAYER_SCREENS: u32 = 1 << 3;
+pub const ALL_LAYERS: u32 = !0;
+
+/// A sub-rectangle of the window in 0..1 coordinates, origin at the top left
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    ///Fn: Pixel Rect: x, y, width and height in pixels for a window of the given size
+    pub fn pixel_rect(&self, window_width: u32, window_height: u32) -> [f32; 4] {
+        let [width, height] = self.pixel_size(window_width, window_height);
+        let x = (self.x * window_width as f32).round();
+        let y = (self.y * window_height as f32).round();
+        // Rounding mustn't push the rectangle past the window edge
+        [
+            x,
+            y,
+            (width as f32).min(window_width as f32 - x),
+            (height as f32).min(window_height as f32 - y),
+        ]
+    }
+
+    fn pixel_size(&self, window_width: u32, window_height: u32) -> [u32; 2] {
+        [
+            ((self.width * window_width as f32).round() as u32).max(1),
+            ((self.height * window_height as f32).round() as u32).max(1),
+        ]
+    }
+}
+
+/// Where a view's image ends up
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ViewTarget {
+    /// Composited into a rectangle of the window, resized with it
+    Viewport(Viewport),
+    /// Kept offscreen at a fixed size, for sampling as a material's texture (mirrors, monitors)
+    Texture { width: u32, height: u32 },
+}
+
+impl ViewTarget {
+    ///Fn: Size: Width and height in pixels of the view's targets
+    fn size(&self, sc_desc: &wgpu::SwapChainDescriptor) -> [u32; 2] {
+        match self {
+            ViewTarget::Viewport(viewport) => viewport.pixel_size(sc_desc.width, sc_desc.height),
+            ViewTarget::Texture { width, height } => [(*width).max(1), (*height).max(1)],
+        }
+    }
+}
+
+pub struct ViewDescriptor<'a> {
+    pub label: &'a str,
+    pub camera: Camera,
+    pub target: ViewTarget,
+    /// None clears to transparent, leaving whatever is behind the view visible
+    pub clear_color: Option<wgpu::Color>,
+    pub layer_mask: u32,
+}
+
+/// An extra camera drawn into its own colour and depth targets, which are then
+/// composited into a rectangle of the window by the ViewCompositor or sampled by materials.
+pub struct View {
+    pub label: String,
+    pub enabled: bool,
+    pub camera: Camera,
+    pub target: ViewTarget,
+    pub clear_color: Option<wgpu::Color>,
+    pub layer_mask: u32,
+    /// The blended draws back to front from this view's eye, rebuilt every frame
+    pub blended: Vec<DrawItem>,
+    camera_uniform: CameraUniform,
+    uniform_buffer: wgpu::Buffer,
+    pub uniform_bind_group: wgpu::BindGroup,
+    pub clustered_lighting: ClusteredLighting,
+    color: texture::Texture,
+    depth: texture::Texture,
+    output_bind_group: wgpu::BindGroup,
+}
+
+impl View {
+    ///Fn: New: Creates a view with its own camera bind group, light clusters and targets
+    ///Params: camera_layout is the layout the scene pipelines expect at group 1
+    pub fn new(
+        device: &wgpu::Device,
+        assets: &Assets,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        desc: ViewDescriptor,
+        camera_layout: &wgpu::BindGroupLayout,
+        compositor: &ViewCompositor,
+        light_buffer: &wgpu::Buffer,
+    ) -> Self {
+        let camera_uniform = CameraUniform::new();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Camera Buffer", desc.label)),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some(&format!("{}_camera_bind_group", desc.label)),
+        });
+        let clustered_lighting = ClusteredLighting::new(device, assets, light_buffer);
+        let (color, depth, output_bind_group) =
+            Self::create_targets(device, sc_desc, &desc.target, compositor, desc.label);
+
+        let mut view = Self {
+            label: desc.label.to_string(),
+            enabled: true,
+            camera: desc.camera,
+            target: desc.target,
+            clear_color: desc.clear_color,
+            layer_mask: desc.layer_mask,
+            blended: Vec::new(),
+            camera_uniform,
+            uniform_buffer,
+            uniform_bind_group,
+            clustered_lighting,
+            color,
+            depth,
+            output_bind_group,
+        };
+        view.update_aspect(sc_desc);
+        view
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        target: &ViewTarget,
+        compositor: &ViewCompositor,
+        label: &str,
+    ) -> (texture::Texture, texture::Texture, wgpu::BindGroup) {
+        let [width, height] = target.size(sc_desc);
+        let view_desc = wgpu::SwapChainDescriptor {
+            width,
+            height,
+            ..*sc_desc
+        };
+        let color = texture::Texture::create_render_target(
+            device,
+            &view_desc,
+            sc_desc.format,
+            1,
+            &format!("{}_color", label),
+        );
+        let depth = texture::Texture::create_depth_texture(
+            device,
+            &view_desc,
+            1,
+            &format!("{}_depth", label),
+        );
+        let output_bind_group = pipeline::create_texture_bind_group(
+            device,
+            &compositor.texture_bind_group_layout,
+            &color,
+            &format!("{}_output_bind_group", label),
+        );
+        (color, depth, output_bind_group)
+    }
+
+    fn update_aspect(&mut self, sc_desc: &wgpu::SwapChainDescriptor) {
+        let [width, height] = self.target.size(sc_desc);
+        self.camera.aspect = width as f32 / height as f32;
+    }
+
+    ///Fn: Output Texture: The view's colour target, for use as a material's diffuse texture
+    ///Params: Only stable for ViewTarget::Texture, viewport targets are recreated on resize
+    pub fn output_texture(&self) -> &texture::Texture {
+        &self.color
+    }
+
+    ///Fn: Resize: Follows the window size, texture targets keep their own resolution
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        compositor: &ViewCompositor,
+    ) {
+        if let ViewTarget::Texture { .. } = self.target {
+            return;
+        }
+        let (color, depth, output_bind_group) =
+            Self::create_targets(device, sc_desc, &self.target, compositor, &self.label);
+        self.color = color;
+        self.depth = depth;
+        self.output_bind_group = output_bind_group;
+        self.update_aspect(sc_desc);
+    }
+
+    ///Fn: Update: Uploads this frame's camera and light clusters
+    ///Params: time is in seconds, as in CameraUniform
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        light_count: u32,
+        time: f32,
+    ) {
+        let [width, height] = self.target.size(sc_desc);
+        let viewport_size = [width as f32, height as f32];
+        self.camera_uniform
+            .update(&self.camera, viewport_size, time);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+        self.clustered_lighting
+            .update(queue, &self.camera, viewport_size, light_count);
+    }
+
+    ///Fn: Begin Render Pass: Clears the view's targets as configured and starts drawing into them
+    pub fn begin_render_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+    ) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("View Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &self.color.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear_color.unwrap_or(wgpu::Color::TRANSPARENT)),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.camera.depth_mode.far_depth()),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+}
+
+/// Copies finished views into their rectangles of the window
+pub struct ViewCompositor {
+    pipeline: wgpu::RenderPipeline,
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl ViewCompositor {
+    pub fn new(
+        device: &wgpu::Device,
+        assets: &Assets,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) -> Self {
+        let texture_bind_group_layout = pipeline::create_texture_bind_group_layout(
+            device,
+            wgpu::ShaderStage::FRAGMENT,
+            "view_texture_bind_group_layout",
+        );
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("View Composite Pipeline Layout"),
+            bind_group_layouts: &[&texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Blit Shader"),
+            source: wgpu::ShaderSource::Wgsl(pipeline::fullscreen_wgsl(
+                assets,
+                &assets.shader("blit.wgsl", include_str!("../shader/blit.wgsl")),
+            )),
+            flags: wgpu::ShaderFlags::default(),
+        });
+        let pipeline = pipeline::create_render_pipeline(
+            device,
+            &layout,
+            &[pipeline::blended_color_target(sc_desc.format)],
+            None,
+            &[],
+            &module,
+            1,
+            "View Composite Pipeline",
+        );
+
+        Self {
+            pipeline,
+            texture_bind_group_layout,
+        }
+    }
+
+    ///Fn: Composite: Draws every enabled viewport view into its rectangle of target_view, in order
+    pub fn composite(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target_view: &wgpu::TextureView,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        views: &[View],
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("View Composite Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        for view in views.iter().filter(|view| view.enabled) {
+            let viewport = match view.target {
+                ViewTarget::Viewport(viewport) => viewport,
+                ViewTarget::Texture { .. } => continue,
+            };
+            let [x, y, width, height] = viewport.pixel_rect(sc_desc.width, sc_desc.height);
+            render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+            render_pass.set_bind_group(0, &view.output_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+///Fn: Render Order: Sorts views so every view is drawn after the views whose textures it samples
+///Params: sampled_layers pairs a layer with the view whose output that layer's materials sample.
+///Views caught in a cycle are appended in declaration order, see unready_layers for what they skip.
+///Call it when the views or the materials sampling them change rather than every frame, it warns about cycles.
+pub fn render_order(views: &[View], sampled_layers: &[(u32, usize)]) -> Vec<usize> {
+    let depends_on = |view: usize, other: usize| {
+        view != other
+            && sampled_layers
+                .iter()
+                .any(|&(layer, source)| source == other && views[view].layer_mask & layer != 0)
+    };
+    let mut order = Vec::with_capacity(views.len());
+    let mut placed = vec![false; views.len()];
+    while order.len() < views.len() {
+        let ready = (0..views.len()).find(|&view| {
+            !placed[view] && (0..views.len()).all(|other| placed[other] || !depends_on(view, other))
+        });
+        match ready {
+            Some(view) => {
+                placed[view] = true;
+                order.push(view);
+            }
+            None => {
+                let cycle = (0..views.len())
+                    .filter(|&view| !placed[view])
+                    .collect::<Vec<_>>();
+                log::warn!(
+                    "Views {} sample each other in a cycle, some screens will be skipped",
+                    cycle
+                        .iter()
+                        .map(|&view| views[view].label.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                order.extend(cycle);
+                break;
+            }
+        }
+    }
+    order
+}
+
+///Fn: Unready Layers: Layers sampling a view that hasn't been drawn yet this frame
+///Params: A pass can't sample the texture it renders to, so check before marking the view being drawn
+pub fn unready_layers(sampled_layers: &[(u32, usize)], rendered: &[bool]) -> u32 {
+    sampled_layers
+        .iter()
+        .filter(|&&(_, source)| !rendered[source])
+        .fold(0, |layers, &(layer, _)| layers | layer)
+}